@@ -0,0 +1,67 @@
+use crate::error::RollError;
+
+/// Starts text that marks what went wrong, in bold red
+const ERROR_COLOR: &str = "\x1b[1;31m";
+/// Starts the margin beside the echoed input, in bold blue
+const MARGIN_COLOR: &str = "\x1b[1;34m";
+/// Ends any color
+const RESET: &str = "\x1b[0m";
+
+/// Describe an error the way a compiler would, with the input echoed beneath it
+/// and carets under the part of the input that caused it
+pub fn diagnostic(input: &str, error: &RollError, color: bool) -> String {
+    let paint = |text: &str, code: &str| if color {
+        format!("{}{}{}", code, text, RESET)
+    } else {
+        text.to_string()
+    };
+    let mut report = paint(&error.to_string(), ERROR_COLOR);
+    let span = match error.span() {
+        // Spans from another input, such as one with variables put in, cannot be shown against this one
+        Some(span) if input.get(span.start..span.end).is_some() => span,
+        _ => return report,
+    };
+    // Carets are lined up by character, so that text before the span may be any width in bytes
+    let column = input[..span.start].chars().count();
+    let width = input[span.start..span.end].chars().count().max(1);
+    let margin = paint("  |", MARGIN_COLOR);
+    report.push_str(&format!("\n{}\n{} {}\n", margin, margin, input));
+    report.push_str(&format!(
+        "{} {}{}",
+        margin,
+        " ".repeat(column),
+        paint(&format!("{} {}", "^".repeat(width), hint(error, span.start == input.len())), ERROR_COLOR),
+    ));
+    report
+}
+
+/// A short note to put beside the carets, saying what is wrong with the part of the input they are under
+fn hint(error: &RollError, at_end: bool) -> &'static str {
+    match error {
+        RollError::TokenizeError { .. } => "not part of any roll",
+        RollError::ParseError { .. } if at_end => "the expression ends too soon",
+        RollError::ParseError { .. } => "not expected here",
+        RollError::EvaluationError { .. } => "this could not be rolled",
+        RollError::DistributionError { .. } => "the outcomes of this could not be weighed",
+        RollError::LimitError { .. } => "this went past a limit",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tokenize::tokenize;
+    use crate::parse::parse;
+    use crate::diagnostic::diagnostic;
+
+    #[test]
+    fn carets_under_span() {
+        let error = parse(tokenize("2d6 + * 3".to_string())).unwrap_err();
+        assert_eq!(
+            "Parse Error: Expected nested start or \"(\", found MultOperator(\"*\")\n  |\n  | 2d6 + * 3\n  |       ^ not expected here",
+            diagnostic("2d6 + * 3", &error, false)
+        );
+        let error = parse(tokenize("2d6+(".to_string())).unwrap_err();
+        assert!(diagnostic("2d6+(", &error, false).ends_with("  |      ^ the expression ends too soon"));
+        assert!(diagnostic("2d6+(", &error, true).starts_with("\x1b[1;31mParse Error"));
+    }
+}
@@ -0,0 +1,921 @@
+use std::collections::BTreeMap;
+use num_bigint::BigInt;
+use num_integer::Integer;
+use num_traits::{Zero, One, ToPrimitive, Float};
+use crate::parse::{Chain, Start, AddLayer, MultLayer, SignLayer, Roll, SubExpression, Number, AddOperator, MultOperator, RollOperator};
+use crate::parse::{Modifier, Comparison, ExplodeOperator, RerollOperator, ComparisonOperator};
+use crate::evaluate::{Thunk, Delayable, Target, MAX_EXPLOSIONS, MAX_REROLLS};
+use crate::error::RollError;
+use crate::limits::{EvalLimits, Budget};
+
+/// The most outcomes that may be weighed at once, so that huge expressions fail rather than hang
+const MAX_OUTCOMES: usize = 1_000_000;
+
+/// The most work that may be done adding up the dice of a roll, for the same reason,
+/// counted in 64-bit words of the weights combined along with a little for each pair of them
+const MAX_WORK: u64 = 1_000_000_000;
+
+/// The work of combining a pair of weights, apart from the words they take up
+const PAIR_WORK: u64 = 16;
+
+/// Work out the exact chance of every outcome of an expression tree
+pub fn distribution(tree: &Thunk<Start>) -> Result<Distribution, RollError> {
+    distribution_with_limits(tree, &EvalLimits::default())
+}
+
+/// Work out the exact chance of every outcome of an expression tree,
+/// stopping with an error if the work goes past any of the limits
+pub fn distribution_with_limits(tree: &Thunk<Start>, limits: &EvalLimits) -> Result<Distribution, RollError> {
+    tree.distribution(&Budget::new(limits))
+}
+
+/// The outcomes of something random, each with a weight saying how likely it is
+#[derive(Debug, Clone, PartialEq)]
+pub struct Distribution<T: Ord = BigInt> {
+    /// The weight of each outcome
+    weights: BTreeMap<T, BigInt>,
+    /// The sum of every weight
+    total: BigInt,
+}
+
+impl<T: Ord> Distribution<T> {
+    /// Every outcome, from lowest to highest, along with its weight
+    pub fn outcomes(&self) -> impl Iterator<Item = (&T, &BigInt)> {
+        self.weights.iter()
+    }
+
+    /// The sum of every weight, which the chance of an outcome is its weight out of
+    pub fn total_weight(&self) -> &BigInt {
+        &self.total
+    }
+
+    /// The chance of an outcome, from 0 to 1
+    pub fn probability(&self, outcome: &T) -> f64 {
+        self.weights.get(outcome).map_or(0.0, |weight| ratio(weight, &self.total))
+    }
+
+    /// The chance of an outcome at least as high as the one given, from 0 to 1
+    pub fn probability_at_least(&self, outcome: &T) -> f64 {
+        let weight: BigInt = self.weights.range(outcome..).map(|(_, weight)| weight).sum();
+        ratio(&weight, &self.total)
+    }
+
+    /// The lowest outcome
+    pub fn min(&self) -> &T {
+        self.weights.keys().next().expect("A distribution always has an outcome")
+    }
+
+    /// The highest outcome
+    pub fn max(&self) -> &T {
+        self.weights.keys().next_back().expect("A distribution always has an outcome")
+    }
+
+    /// The lowest outcome that at least the given percent of outcomes are at or below
+    pub fn percentile(&self, percent: f64) -> &T {
+        // The percent is exactly mantissa * 2^exponent, so the threshold can be checked without rounding
+        let (mantissa, exponent, _) = percent.integer_decode();
+        let threshold = BigInt::from(mantissa) * &self.total;
+        let mut weight = BigInt::zero();
+        for (outcome, outcome_weight) in self.weights.iter() {
+            weight += outcome_weight;
+            let reached = if exponent < 0 {
+                (&weight * 100) << (-exponent as usize) >= threshold
+            } else {
+                &weight * 100 >= &threshold << (exponent as usize)
+            };
+            if reached {
+                return outcome;
+            }
+        }
+        self.max()
+    }
+
+    /// The outcome that half of outcomes are at or below
+    pub fn median(&self) -> &T {
+        self.percentile(50.0)
+    }
+}
+
+impl Distribution {
+    /// The expected value of the outcome
+    pub fn mean(&self) -> f64 {
+        let sum: BigInt = self.weights.iter().map(|(outcome, weight)| outcome * weight).sum();
+        ratio(&sum, &self.total)
+    }
+
+    /// The expected square of how far the outcome is from the mean
+    pub fn variance(&self) -> f64 {
+        let sum: BigInt = self.weights.iter().map(|(outcome, weight)| outcome * weight).sum();
+        let sum_of_squares: BigInt = self.weights.iter().map(|(outcome, weight)| outcome * outcome * weight).sum();
+        // Worked out exactly as (total * sum of squares - sum squared) / total squared
+        ratio(&(&self.total * sum_of_squares - &sum * &sum), &(&self.total * &self.total))
+    }
+
+    /// The typical distance of the outcome from the mean
+    pub fn standard_deviation(&self) -> f64 {
+        self.variance().sqrt()
+    }
+}
+
+/// Divide one BigInt by another into a float, even when neither fits in one
+pub fn ratio(numerator: &BigInt, denominator: &BigInt) -> f64 {
+    // Throw away the low bits, which a float could not hold anyway
+    let shift = denominator.bits().saturating_sub(64);
+    let numerator = (numerator >> shift).to_f64().unwrap_or(f64::NAN);
+    let denominator = (denominator >> shift).to_f64().unwrap_or(f64::NAN);
+    numerator / denominator
+}
+
+/// The error given when an expression has too many outcomes to weigh
+fn too_many_outcomes() -> RollError {
+    RollError::distribution("Too many outcomes to compute exactly")
+}
+
+impl<T: Ord + Clone> Distribution<T> {
+    /// Create a distribution with a single outcome
+    fn certain(outcome: T) -> Self {
+        let mut weights = BTreeMap::new();
+        weights.insert(outcome, BigInt::one());
+        Self {
+            weights,
+            total: BigInt::one(),
+        }
+    }
+
+    /// Create a distribution where every outcome is equally likely
+    pub(crate) fn uniform(outcomes: impl IntoIterator<Item = T>) -> Self {
+        let mut weights = BTreeMap::new();
+        for outcome in outcomes {
+            *weights.entry(outcome).or_insert_with(BigInt::zero) += 1;
+        }
+        Self::reduced(weights)
+    }
+
+    /// Create a distribution from its weights, dividing out any common factor
+    fn reduced(mut weights: BTreeMap<T, BigInt>) -> Self {
+        let total: BigInt = weights.values().sum();
+        let divisor = weights.values().fold(total.clone(), |divisor, weight| divisor.gcd(weight));
+        if divisor > BigInt::one() {
+            for weight in weights.values_mut() {
+                *weight /= &divisor;
+            }
+            Self {
+                weights,
+                total: total / divisor,
+            }
+        } else {
+            Self { weights, total }
+        }
+    }
+
+    /// The only outcome, if there is exactly one
+    fn only(&self) -> Option<&T> {
+        match self.weights.len() {
+            1 => self.weights.keys().next(),
+            _ => None,
+        }
+    }
+
+    /// Change every outcome, adding together the weights of any that become the same
+    fn map<U: Ord + Clone>(&self, f: impl Fn(&T) -> U) -> Distribution<U> {
+        let mut weights = BTreeMap::new();
+        for (outcome, weight) in self.weights.iter() {
+            *weights.entry(f(outcome)).or_insert_with(BigInt::zero) += weight;
+        }
+        Distribution::reduced(weights)
+    }
+
+    /// Replace every outcome with a distribution of its own, weighted by the outcome it replaced
+    fn bind<U: Ord + Clone>(&self, budget: &Budget, mut f: impl FnMut(&T) -> Result<Distribution<U>, RollError>) -> Result<Distribution<U>, RollError> {
+        budget.check_time()?;
+        // Bring every part over the same total so their weights can be added
+        let mut common = BigInt::one();
+        let mut work = 0;
+        let mut parts = Vec::new();
+        for (outcome, weight) in self.weights.iter() {
+            let part = f(outcome)?;
+            common = common.lcm(&part.total);
+            spend(&mut work, part.weights.len(), common.bits() + weight.bits())?;
+            parts.push((weight, part));
+        }
+        let mut weights = BTreeMap::new();
+        for (weight, part) in parts {
+            let scale = weight * (&common / &part.total);
+            for (outcome, part_weight) in part.weights {
+                *weights.entry(outcome).or_insert_with(BigInt::zero) += &scale * part_weight;
+            }
+            if weights.len() > MAX_OUTCOMES {
+                return Err(too_many_outcomes());
+            }
+        }
+        Ok(Distribution::reduced(weights))
+    }
+
+    /// Combine every pair of outcomes from two independent distributions
+    fn combine<U: Ord + Clone, V: Ord + Clone>(&self, other: &Distribution<U>, budget: &Budget, f: impl Fn(&T, &U) -> V) -> Result<Distribution<V>, RollError> {
+        let combined = self.combine_unreduced(other, budget, f)?;
+        Ok(Distribution::reduced(combined.weights))
+    }
+
+    /// Combine every pair of outcomes without dividing out any common factor,
+    /// which is left to be done once at the end of a long run of combining
+    fn combine_unreduced<U: Ord + Clone, V: Ord + Clone>(&self, other: &Distribution<U>, budget: &Budget, f: impl Fn(&T, &U) -> V) -> Result<Distribution<V>, RollError> {
+        budget.check_time()?;
+        if self.weights.len().saturating_mul(other.weights.len()) > MAX_OUTCOMES {
+            return Err(too_many_outcomes());
+        }
+        let mut weights = BTreeMap::new();
+        for (left, left_weight) in self.weights.iter() {
+            for (right, right_weight) in other.weights.iter() {
+                *weights.entry(f(left, right)).or_insert_with(BigInt::zero) += left_weight * right_weight;
+            }
+        }
+        Ok(Distribution {
+            weights,
+            total: &self.total * &other.total,
+        })
+    }
+
+    /// The distribution of combining a number of independent copies of this one, starting from an empty one
+    fn repeated(&self, count: usize, empty: T, work: &mut u64, budget: &Budget, f: impl Fn(&T, &T) -> T) -> Result<Self, RollError> {
+        let mut result = Self::certain(empty);
+        for _ in 0..count {
+            spend(work, result.weights.len() * self.weights.len(), result.total.bits() + self.total.bits())?;
+            result = result.combine_unreduced(self, budget, &f)?;
+        }
+        Ok(Self::reduced(result.weights))
+    }
+}
+
+/// The sum of a growing number of independent copies of a distribution,
+/// kept as the weight of every whole number from the lowest sum to the highest
+struct RunningSum<'a> {
+    /// The distribution of a single copy
+    single: &'a Distribution,
+    /// How many copies have been added so far
+    count: usize,
+    /// The weight of each sum, starting from the lowest
+    weights: Vec<BigInt>,
+    /// The sum of every weight, which none of them can be larger than
+    total: BigInt,
+}
+
+impl<'a> RunningSum<'a> {
+    /// Start a sum of no copies at all
+    fn new(single: &'a Distribution) -> Self {
+        Self {
+            single,
+            count: 0,
+            weights: vec![BigInt::one()],
+            total: BigInt::one(),
+        }
+    }
+
+    /// The distribution of adding together a number of copies, adding more to the sum as needed
+    fn up_to(&mut self, count: &BigInt, work: &mut u64, budget: &Budget) -> Result<Distribution, RollError> {
+        let count = count.to_usize().ok_or_else(too_many_outcomes)?;
+        let lowest = self.single.min();
+        let width = match (self.single.max() - lowest).to_usize().filter(|width| *width < MAX_OUTCOMES) {
+            Some(width) => width,
+            // Outcomes spread too far apart to lay out one after another are combined one pair at a time
+            None => return self.single.repeated(count, BigInt::zero(), work, budget, |left, right| left + right),
+        };
+        if count < self.count {
+            *self = Self::new(self.single);
+        }
+        let offsets: Vec<_> = self.single.outcomes().map(|(outcome, weight)| ((outcome - lowest).to_usize().unwrap(), weight)).collect();
+        // A die whose every face is as likely as the others adds a window of the sum so far to each new sum
+        let uniform = offsets.len() == width + 1 && offsets.iter().all(|(_, weight)| weight.is_one());
+        while self.count < count {
+            budget.check_time()?;
+            if self.weights.len() + width > MAX_OUTCOMES {
+                return Err(too_many_outcomes());
+            }
+            self.total *= &self.single.total;
+            let bits = self.total.bits();
+            let mut weights = vec![BigInt::zero(); self.weights.len() + width];
+            if uniform {
+                spend(work, 2 * weights.len(), bits)?;
+                let mut window = BigInt::zero();
+                for (index, weight) in weights.iter_mut().enumerate() {
+                    if let Some(entering) = self.weights.get(index) {
+                        window += entering;
+                    }
+                    if index > width {
+                        window -= &self.weights[index - width - 1];
+                    }
+                    *weight = window.clone();
+                }
+            } else {
+                spend(work, self.weights.len() * offsets.len(), bits)?;
+                for (index, weight) in self.weights.iter().enumerate() {
+                    for &(offset, single_weight) in offsets.iter() {
+                        weights[index + offset] += weight * single_weight;
+                    }
+                }
+            }
+            self.weights = weights;
+            self.count += 1;
+        }
+        // Every outcome is weighed again by whatever the sum is used for
+        spend(work, self.weights.len(), self.total.bits())?;
+        let lowest = lowest * count;
+        let weights = self.weights.iter().enumerate()
+            .filter(|(_, weight)| !weight.is_zero())
+            .map(|(index, weight)| (&lowest + index, weight.clone()))
+            .collect();
+        // The sum is left for the caller to reduce, which only needs doing once
+        Ok(Distribution {
+            weights,
+            total: self.total.clone(),
+        })
+    }
+}
+
+/// Count work towards the limit for a roll, from how many pairs of weights are about to be combined
+/// and how many bits the largest of them could take up
+fn spend(work: &mut u64, pairs: usize, bits: u64) -> Result<(), RollError> {
+    *work = work.saturating_add((pairs as u64).saturating_mul(PAIR_WORK + bits / 64));
+    match *work > MAX_WORK {
+        true => Err(too_many_outcomes()),
+        false => Ok(()),
+    }
+}
+
+/// A trait that represents something whose every outcome can be weighed
+pub trait Distributable {
+    fn distribution(&self, budget: &Budget) -> Result<Distribution, RollError>;
+}
+
+impl<T: Delayable + Distributable> Distributable for Thunk<T> {
+    fn distribution(&self, budget: &Budget) -> Result<Distribution, RollError> {
+        self.weighed(self.delayed().distribution(budget), budget)
+    }
+}
+
+impl<T: Delayable> Thunk<T> {
+    /// Check the outcomes of the stored computation, blaming this part of the input for any error
+    fn weighed(&self, distribution: Result<Distribution, RollError>, budget: &Budget) -> Result<Distribution, RollError> {
+        let distribution = distribution.and_then(|distribution| {
+            // The outcomes furthest from 0 are at either end
+            budget.check_value(distribution.min())?;
+            budget.check_value(distribution.max())?;
+            Ok(distribution)
+        });
+        distribution.map_err(|error| error.or_span(self.span()))
+    }
+}
+
+impl Distributable for Start {
+    fn distribution(&self, budget: &Budget) -> Result<Distribution, RollError> {
+        match self {
+            Self::Base(base_thunk) => base_thunk.distribution(budget),
+        }
+    }
+}
+
+impl Distributable for AddLayer {
+    fn distribution(&self, budget: &Budget) -> Result<Distribution, RollError> {
+        let (first_thunk, links) = self.links();
+        let mut left = first_thunk.distribution(budget)?;
+        for (holder, op, right_thunk) in links {
+            let right = right_thunk.distribution(budget)?;
+            let combined = match op {
+                AddOperator::Add => left.combine(&right, budget, |left, right| left + right),
+                AddOperator::Subtract => left.combine(&right, budget, |left, right| left - right),
+            };
+            left = match holder {
+                Some(holder) => holder.weighed(combined, budget)?,
+                None => combined?,
+            };
+        }
+        Ok(left)
+    }
+}
+
+impl Distributable for MultLayer {
+    fn distribution(&self, budget: &Budget) -> Result<Distribution, RollError> {
+        let (first_thunk, links) = self.links();
+        let mut left = first_thunk.distribution(budget)?;
+        for (holder, op, right_thunk) in links {
+            let right = right_thunk.distribution(budget)?;
+            let combined = match op {
+                MultOperator::Multiply => left.combine(&right, budget, |left, right| left * right),
+                MultOperator::Divide => {
+                    if right.weights.contains_key(&BigInt::zero()) {
+                        return Err(RollError::distribution("Right side of a division could be 0").or_span(right_thunk.span()));
+                    }
+                    left.combine(&right, budget, |left, right| left / right)
+                },
+            };
+            left = match holder {
+                Some(holder) => holder.weighed(combined, budget)?,
+                None => combined?,
+            };
+        }
+        Ok(left)
+    }
+}
+
+impl Distributable for SignLayer {
+    fn distribution(&self, budget: &Budget) -> Result<Distribution, RollError> {
+        match self {
+            Self::Base(base_thunk) => base_thunk.distribution(budget),
+            Self::Sign(op, signed_thunk) => {
+                let signed = signed_thunk.distribution(budget)?;
+                match op {
+                    AddOperator::Add => Ok(signed),
+                    AddOperator::Subtract => Ok(signed.map(|outcome| -outcome)),
+                }
+            },
+        }
+    }
+}
+
+impl Distributable for SubExpression {
+    fn distribution(&self, budget: &Budget) -> Result<Distribution, RollError> {
+        match self {
+            Self::Base(number_thunk) => number_thunk.distribution(budget),
+            Self::Recurse(start_thunk) => start_thunk.distribution(budget),
+        }
+    }
+}
+
+impl Distributable for Number {
+    fn distribution(&self, _budget: &Budget) -> Result<Distribution, RollError> {
+        Ok(Distribution::certain(self.value()?))
+    }
+}
+
+/// Find the value of something that must not be random
+fn constant<T: Delayable + Distributable>(thunk: &Thunk<T>, budget: &Budget) -> Result<BigInt, RollError> {
+    match thunk.distribution(budget)?.only() {
+        Some(value) => Ok(value.clone()),
+        None => Err(RollError::distribution("Expected a constant, found an expression with more than one outcome").or_span(thunk.span())),
+    }
+}
+
+impl Comparison {
+    /// Find the target of the Comparison, which must not be random
+    fn constant_target(&self, budget: &Budget) -> Result<Target, RollError> {
+        let Self::Compare(op, value_thunk) = self;
+        Ok(Target { op: *op, value: constant(value_thunk, budget)? })
+    }
+}
+
+impl Distributable for Roll {
+    fn distribution(&self, budget: &Budget) -> Result<Distribution, RollError> {
+        match self {
+            // A lone sub-expression is not a pool of dice
+            Self::Base(base_thunk) => base_thunk.distribution(budget),
+            _ => self.pools(budget),
+        }
+    }
+}
+
+/// Type alias for the dice of a pool, sorted from lowest to highest, along with how many sides they had
+type SortedPool = (Vec<BigInt>, Option<BigInt>);
+
+/// Type alias for what a single die becomes under a modifier, from what it showed
+type DieChange = Box<dyn Fn(&BigInt) -> Distribution<Vec<BigInt>>>;
+
+/// Every way the dice of a roll could come out
+enum Pools {
+    /// Each die was rolled on its own, and may since have become several dice
+    Independent {
+        /// How many dice were rolled, and how many sides they had
+        rolls: Distribution<(BigInt, Option<BigInt>)>,
+        /// What a single die became, for each number of sides
+        dice: BTreeMap<Option<BigInt>, Distribution<Vec<BigInt>>>,
+        /// Whether each die has already been turned into what it adds to the total
+        reduced: bool,
+    },
+    /// Every die that is still counted
+    Combined(Distribution<SortedPool>),
+}
+
+/// How the dice that are still counted are turned into a total
+#[derive(Clone)]
+struct Counting {
+    /// The target a die must meet to count as a success, if successes are being counted
+    successes: Option<Target>,
+    /// The target a die must meet to count as a failure, if failures are being counted
+    failures: Option<Target>,
+}
+
+impl Counting {
+    /// What a single die adds to the total
+    fn contribution(&self, die: &BigInt) -> BigInt {
+        match self.successes {
+            Some(ref successes) => {
+                let failed = self.failures.as_ref().is_some_and(|failures| failures.matches(die));
+                BigInt::from(successes.matches(die) as i8 - failed as i8)
+            },
+            None => die.clone(),
+        }
+    }
+}
+
+/// Put two sorted lists of dice together, keeping them sorted
+fn merged(left: &[BigInt], right: &[BigInt]) -> Vec<BigInt> {
+    let mut dice = [left, right].concat();
+    dice.sort();
+    dice
+}
+
+impl Roll {
+    /// Weigh every total the dice of the roll could come to
+    fn pools(&self, budget: &Budget) -> Result<Distribution, RollError> {
+        // Walk back to where the dice came from, collecting what was done to them since
+        let mut steps = Vec::new();
+        let mut source = self;
+        loop {
+            match source {
+                Self::Recurse(left_thunk, op, _) if *op != RollOperator::D => {
+                    steps.push(source);
+                    source = left_thunk.delayed();
+                },
+                Self::Modify(left_thunk, _) => {
+                    steps.push(source);
+                    source = left_thunk.delayed();
+                },
+                _ => break,
+            }
+        }
+        steps.reverse();
+
+        // Successes and failures are only counted once every step is done
+        let mut counting = Counting { successes: None, failures: None };
+        for step in steps.iter() {
+            match step {
+                Self::Modify(_, Modifier::Success(comparison)) => counting.successes = Some(comparison.constant_target(budget)?),
+                Self::Modify(_, Modifier::Failure(comparison)) => {
+                    if counting.successes.is_none() {
+                        return Err(RollError::distribution("Failures can only be counted after a success target"));
+                    }
+                    counting.failures = Some(comparison.constant_target(budget)?);
+                },
+                _ => (),
+            }
+        }
+        // Only the steps that change the dice themselves matter from here on
+        steps.retain(|step| !matches!(step, Self::Modify(_, Modifier::Success(_)) | Self::Modify(_, Modifier::Failure(_))));
+
+        let mut pools = source.source_pools(budget)?;
+        for (index, step) in steps.iter().enumerate() {
+            // Once no later step looks at the dice, each die can be reduced to what it adds to the total
+            let last = index + 1 == steps.len();
+            pools = pools.apply(step, &counting, last, budget)?;
+        }
+        pools.totals(&counting, budget)
+    }
+
+    /// Weigh every way the dice could come out before anything is done to them
+    fn source_pools(&self, budget: &Budget) -> Result<Pools, RollError> {
+        match self {
+            Self::Recurse(left_thunk, RollOperator::D, right_thunk) => {
+                let count = left_thunk.distribution(budget)?;
+                let sides = right_thunk.distribution(budget)?;
+                if *count.min() < BigInt::zero() {
+                    return Err(RollError::distribution(format!("Left side of a roll could be less than 0, found {}", count.min())).or_span(left_thunk.span()));
+                }
+                budget.check_dice(count.max())?;
+                if *sides.min() < BigInt::one() {
+                    return Err(RollError::distribution(format!("Right side of a roll could be less than 1, found {}", sides.min())).or_span(right_thunk.span()));
+                }
+                let mut dice = BTreeMap::new();
+                for (side_count, _) in sides.outcomes() {
+                    if side_count.to_usize().is_none_or(|side_count| side_count > MAX_OUTCOMES) {
+                        return Err(too_many_outcomes());
+                    }
+                    dice.insert(Some(side_count.clone()), Distribution::uniform(faces(side_count).map(|face| vec![face])));
+                }
+                Ok(Pools::Independent {
+                    rolls: count.combine(&sides, budget, |count, sides| (count.clone(), Some(sides.clone())))?,
+                    dice,
+                    reduced: false,
+                })
+            },
+            _ => {
+                // A single value that is modified is treated as a single die with no sides
+                let mut dice = BTreeMap::new();
+                dice.insert(None, self.distribution(budget)?.map(|value| vec![value.clone()]));
+                Ok(Pools::Independent {
+                    rolls: Distribution::certain((BigInt::one(), None)),
+                    dice,
+                    reduced: false,
+                })
+            },
+        }
+    }
+}
+
+/// Every face of a die, from lowest to highest
+fn faces(sides: &BigInt) -> impl Iterator<Item = BigInt> {
+    let sides = sides.clone();
+    std::iter::successors(Some(BigInt::one()), |face| Some(face + 1)).take_while(move |face| *face <= sides)
+}
+
+impl Pools {
+    /// Apply a keep, drop or modifier to every way the dice could come out
+    fn apply(self, step: &Roll, counting: &Counting, last: bool, budget: &Budget) -> Result<Self, RollError> {
+        match step {
+            Roll::Recurse(_, op, right_thunk) => {
+                let count = constant(right_thunk, budget)?;
+                if count < BigInt::zero() {
+                    return Err(RollError::distribution(format!("Right side of a keep or drop was less than 0, found {}", count)));
+                }
+                let count = count.to_usize().unwrap_or(usize::MAX);
+                // Dice are sorted from lowest to highest, so keep from the correct end
+                let keep = |dice: &Vec<BigInt>| {
+                    let count = count.min(dice.len());
+                    match op {
+                        RollOperator::KeepHighest => dice[dice.len() - count..].to_vec(),
+                        RollOperator::KeepLowest => dice[..count].to_vec(),
+                        RollOperator::DropHighest => dice[..dice.len() - count].to_vec(),
+                        RollOperator::DropLowest => dice[count..].to_vec(),
+                        RollOperator::D => unreachable!(),
+                    }
+                };
+                Ok(Pools::Combined(
+                    self.combined(budget)?.map(|(dice, sides)| (keep(dice), sides.clone()))
+                ))
+            },
+            Roll::Modify(_, modifier) => match self {
+                Pools::Independent { rolls, dice, .. } => {
+                    let reduce = if last { Some(counting) } else { None };
+                    let mut changed = BTreeMap::new();
+                    for (sides, die) in dice {
+                        let change = modifier.per_die(sides.as_ref(), reduce, budget)?;
+                        changed.insert(sides, die.bind(budget, |group| change.group(group, reduce, budget))?);
+                    }
+                    Ok(Pools::Independent { rolls, dice: changed, reduced: last })
+                },
+                Pools::Combined(pools) => Ok(Pools::Combined(pools.bind(budget, |(dice, sides)| {
+                    let change = modifier.per_die(sides.as_ref(), None, budget)?;
+                    Ok(change.group(dice, None, budget)?.map(|dice| (dice.clone(), sides.clone())))
+                })?)),
+            },
+            Roll::Base(_) => unreachable!(),
+        }
+    }
+
+    /// Put the dice of each roll together, so that they can be kept or dropped
+    fn combined(self, budget: &Budget) -> Result<Distribution<SortedPool>, RollError> {
+        match self {
+            Pools::Independent { rolls, dice, .. } => {
+                let mut work = 0;
+                rolls.bind(budget, |(count, sides)| {
+                    let count = count.to_usize().ok_or_else(too_many_outcomes)?;
+                    let pool = dice[sides].repeated(count, Vec::new(), &mut work, budget, |left, right| merged(left, right))?;
+                    Ok(pool.map(|dice| (dice.clone(), sides.clone())))
+                })
+            },
+            Pools::Combined(pools) => Ok(pools),
+        }
+    }
+
+    /// Weigh every total the dice could come to
+    fn totals(self, counting: &Counting, budget: &Budget) -> Result<Distribution, RollError> {
+        match self {
+            Pools::Independent { rolls, dice, reduced } => {
+                let contributions: BTreeMap<_, _> = dice.iter().map(|(sides, die)| {
+                    let contribution = die.map(|group| match reduced {
+                        true => group[0].clone(),
+                        false => group.iter().map(|die| counting.contribution(die)).sum(),
+                    });
+                    (sides.clone(), contribution)
+                }).collect();
+                // Each number of dice carries on from the sum of fewer, since they are weighed from fewest to most
+                let mut sums: BTreeMap<_, _> = contributions.iter().map(|(sides, contribution)| (sides, RunningSum::new(contribution))).collect();
+                let mut work = 0;
+                rolls.bind(budget, |(count, sides)| sums.get_mut(sides).unwrap().up_to(count, &mut work, budget))
+            },
+            Pools::Combined(pools) => Ok(pools.map(|(dice, _)| dice.iter().map(|die| counting.contribution(die)).sum())),
+        }
+    }
+}
+
+/// What can become of a single die under a modifier
+struct PerDie {
+    /// Which dice the modifier applies to
+    trigger: Target,
+    /// What each die the modifier applies to becomes, from what it showed
+    change: DieChange,
+}
+
+impl PerDie {
+    /// Apply the modifier to each die in a group, which are independent of each other
+    fn group(&self, group: &[BigInt], reduce: Option<&Counting>, budget: &Budget) -> Result<Distribution<Vec<BigInt>>, RollError> {
+        let mut result = Distribution::certain(Vec::new());
+        for die in group {
+            let changed = if self.trigger.matches(die) {
+                (self.change)(die)
+            } else {
+                Distribution::certain(single(die.clone(), reduce))
+            };
+            result = result.combine(&changed, budget, |left, right| joined(left, right, reduce))?;
+        }
+        Ok(result)
+    }
+}
+
+/// A group made of a single die, or what it adds to the total if it is being reduced
+fn single(die: BigInt, reduce: Option<&Counting>) -> Vec<BigInt> {
+    match reduce {
+        Some(counting) => vec![counting.contribution(&die)],
+        None => vec![die],
+    }
+}
+
+/// Put two groups of dice together, or add together what they add to the total if they are being reduced
+fn joined(left: &[BigInt], right: &[BigInt], reduce: Option<&Counting>) -> Vec<BigInt> {
+    match reduce {
+        Some(_) => vec![left.iter().chain(right).sum()],
+        None => merged(left, right),
+    }
+}
+
+impl Modifier {
+    /// Work out what becomes of a single die with the given number of sides
+    fn per_die(&self, sides: Option<&BigInt>, reduce: Option<&Counting>, budget: &Budget) -> Result<PerDie, RollError> {
+        match self {
+            Self::Explode(op, comparison) => {
+                let sides = match sides {
+                    Some(sides) => sides.clone(),
+                    None => return Err(RollError::distribution("Only dice can explode, found a constant")),
+                };
+                // By default, dice explode on their highest face
+                let trigger = match comparison {
+                    Some(comparison) => comparison.constant_target(budget)?,
+                    None => Target { op: ComparisonOperator::Equal, value: sides.clone() },
+                };
+                let compound = *op == ExplodeOperator::Compound;
+                // Each penetrating die is worth one less than it shows
+                let worth = |face: &BigInt| match op {
+                    ExplodeOperator::Penetrate => face - 1,
+                    _ => face.clone(),
+                };
+                // Compounded dice are added up before anything else is done with them
+                let chain_reduce = if compound { None } else { reduce };
+                let link = |die: BigInt| match compound {
+                    true => vec![die],
+                    false => single(die, chain_reduce),
+                };
+                let extend = |die: BigInt, rest: &[BigInt]| match compound {
+                    true => vec![&die + &rest[0]],
+                    false => joined(&single(die, chain_reduce), rest, chain_reduce),
+                };
+                // Work backwards from the last explosion that is allowed
+                let mut chain = Distribution::uniform(faces(&sides).map(|face| link(worth(&face))));
+                for _ in 1..MAX_EXPLOSIONS {
+                    chain = Distribution::uniform(faces(&sides)).bind(budget, |face| Ok(match trigger.matches(face) {
+                        true => chain.map(|rest| extend(worth(face), rest)),
+                        false => Distribution::certain(link(worth(face))),
+                    }))?;
+                }
+                let reduce = reduce.cloned();
+                Ok(PerDie {
+                    trigger: trigger.clone(),
+                    change: Box::new(move |die| match compound {
+                        true => chain.map(|rest| single(die + &rest[0], reduce.as_ref())),
+                        false => chain.map(|rest| joined(&single(die.clone(), reduce.as_ref()), rest, reduce.as_ref())),
+                    }),
+                })
+            },
+            Self::Reroll(op, comparison) => {
+                let sides = match sides {
+                    Some(sides) => sides.clone(),
+                    None => return Err(RollError::distribution("Only dice can be rerolled, found a constant")),
+                };
+                // By default, dice are rerolled on their lowest face
+                let trigger = match comparison {
+                    Some(comparison) => comparison.constant_target(budget)?,
+                    None => Target { op: ComparisonOperator::Equal, value: BigInt::one() },
+                };
+                let limit = match op {
+                    RerollOperator::Reroll => MAX_REROLLS,
+                    RerollOperator::RerollOnce => 1,
+                };
+                // Work backwards from the last reroll that is allowed, which is always kept
+                let mut last = Distribution::uniform(faces(&sides));
+                for _ in 1..limit {
+                    last = Distribution::uniform(faces(&sides)).bind(budget, |face| Ok(match trigger.matches(face) {
+                        true => last.clone(),
+                        false => Distribution::certain(face.clone()),
+                    }))?;
+                }
+                let reduce = reduce.cloned();
+                Ok(PerDie {
+                    trigger: trigger.clone(),
+                    change: Box::new(move |_| last.map(|face| single(face.clone(), reduce.as_ref()))),
+                })
+            },
+            Self::Success(_) | Self::Failure(_) => unreachable!(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use num_bigint::BigInt;
+    use crate::tokenize::tokenize;
+    use crate::parse::parse;
+    use crate::distribution::{distribution, distribution_with_limits, Distribution};
+    use crate::limits::EvalLimits;
+
+    /// Weigh every outcome of an expression from its text
+    fn weigh(input: &str) -> Distribution {
+        distribution(&parse(tokenize(input.to_string())).unwrap()[0]).unwrap()
+    }
+
+    /// Check that an outcome has exactly the expected chance
+    fn assert_chance(distribution: &Distribution, outcome: i64, numerator: i64, denominator: i64) {
+        let weight = distribution.outcomes()
+            .find(|(value, _)| **value == BigInt::from(outcome))
+            .map_or(BigInt::from(0), |(_, weight)| weight.clone());
+        assert_eq!(weight * denominator, distribution.total_weight() * numerator);
+    }
+
+    #[test]
+    fn sum_of_dice() {
+        let distribution = weigh("2d6+3");
+        assert_eq!(11, distribution.outcomes().count());
+        assert_chance(&distribution, 10, 1, 6);
+        assert_chance(&distribution, 15, 1, 36);
+    }
+
+    #[test]
+    fn nested_roll() {
+        let distribution = weigh("(1d4)d6");
+        assert_chance(&distribution, 24, 1, 4 * 6 * 6 * 6 * 6);
+        assert_chance(&distribution, 1, 1, 4 * 6);
+    }
+
+    #[test]
+    fn keep_highest() {
+        let distribution = weigh("4d6kh3");
+        assert_chance(&distribution, 18, 21, 1296);
+        assert_chance(&distribution, 3, 1, 1296);
+    }
+
+    #[test]
+    fn explode_and_count_successes() {
+        assert_chance(&weigh("1d6!"), 7, 1, 36);
+        assert_chance(&weigh("1d6!"), 6, 0, 1);
+        assert_chance(&weigh("2d10>=7f1"), -2, 1, 100);
+        assert_chance(&weigh("2d10>=7f1"), 2, 16, 100);
+    }
+
+    #[test]
+    fn statistics() {
+        let distribution = weigh("2d6+3");
+        assert_eq!(BigInt::from(5), *distribution.min());
+        assert_eq!(BigInt::from(15), *distribution.max());
+        assert_eq!(BigInt::from(10), *distribution.median());
+        assert_eq!(BigInt::from(7), *distribution.percentile(10.0));
+        // 29 out of 50 is exactly 58 percent, which rounding a float would miss
+        assert_eq!(BigInt::from(29), *weigh("1d50").percentile(58.0));
+        assert!((distribution.mean() - 10.0).abs() < 1e-9);
+        assert!((distribution.variance() - 35.0 / 6.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn negative_roll() {
+        let distribution = weigh("-1d4 + 1");
+        assert_eq!(BigInt::from(-3), *distribution.min());
+        assert_chance(&distribution, 0, 1, 4);
+    }
+
+    #[test]
+    fn limits() {
+        let weigh_within = |input: &str, limits: &EvalLimits| distribution_with_limits(&parse(tokenize(input.to_string())).unwrap()[0], limits);
+        let dice = EvalLimits { max_dice: Some(BigInt::from(4)), ..EvalLimits::unlimited() };
+        assert!(weigh_within("2d6+2d6", &dice).is_ok());
+        assert_eq!("limit", weigh_within("(1d5)d6", &dice).unwrap_err().kind());
+        let bits = EvalLimits { max_bits: Some(8), ..EvalLimits::unlimited() };
+        assert_eq!("limit", weigh_within("1d6*100", &bits).unwrap_err().kind());
+        let time = EvalLimits { max_time: Some(std::time::Duration::ZERO), ..EvalLimits::unlimited() };
+        assert_eq!("limit", weigh_within("1000d6", &time).unwrap_err().kind());
+    }
+
+    #[test]
+    fn division_by_zero() {
+        assert!(distribution(&parse(tokenize("1/(1d2-1)".to_string())).unwrap()[0]).is_err());
+        // 0 is one outcome in 6 to the 30th, far too unlikely to show up as a float
+        assert!(distribution(&parse(tokenize("1/(30d6-30)".to_string())).unwrap()[0]).is_err());
+    }
+
+    #[test]
+    fn sums_of_many_dice() {
+        let sum = weigh("200d6");
+        assert_eq!(BigInt::from(6).pow(200), *sum.total_weight());
+        assert_eq!(
+            vec![(BigInt::from(200), BigInt::from(1)), (BigInt::from(201), BigInt::from(200))],
+            sum.outcomes().take(2).map(|(outcome, weight)| (outcome.clone(), weight.clone())).collect::<Vec<_>>()
+        );
+        // Rerolling once makes the faces unequal
+        assert_chance(&weigh("2d4ro1"), 2, 1, 256);
+        assert_chance(&weigh("2d4ro1"), 8, 25, 256);
+    }
+}
@@ -0,0 +1,123 @@
+/// A range of bytes in the input, from the start up to but not including the end
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Span {
+    /// The offset of the first byte in the range
+    pub start: usize,
+    /// The offset just past the last byte in the range
+    pub end: usize,
+}
+
+impl Span {
+    /// Create a span from its start and end offsets
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+}
+
+/// Something that went wrong while reading or rolling an expression
+#[derive(Debug, Clone, PartialEq)]
+pub enum RollError {
+    /// Part of the input is not something the language understands
+    TokenizeError { message: String, span: Option<Span> },
+    /// The input is not arranged in a way the grammar allows
+    ParseError { message: String, span: Option<Span> },
+    /// The expression could not be rolled
+    EvaluationError { message: String, span: Option<Span> },
+    /// The chance of every outcome of the expression could not be worked out
+    DistributionError { message: String, span: Option<Span> },
+    /// Reading or rolling the expression would take more work than it is allowed
+    LimitError { message: String, span: Option<Span> },
+}
+
+impl RollError {
+    /// Create an error for input that could not be understood
+    pub fn tokenize(message: impl Into<String>, span: Span) -> Self {
+        Self::TokenizeError { message: message.into(), span: Some(span) }
+    }
+
+    /// Create an error for input that does not follow the grammar
+    pub fn parse(message: impl Into<String>, span: Span) -> Self {
+        Self::ParseError { message: message.into(), span: Some(span) }
+    }
+
+    /// Create an error for an expression that could not be rolled, which is pointed
+    /// at the sub-expression it came from once that is known
+    pub fn evaluation(message: impl Into<String>) -> Self {
+        Self::EvaluationError { message: message.into(), span: None }
+    }
+
+    /// Create an error for an expression whose outcomes could not be weighed, which is
+    /// pointed at the sub-expression it came from once that is known
+    pub fn distribution(message: impl Into<String>) -> Self {
+        Self::DistributionError { message: message.into(), span: None }
+    }
+
+    /// Create an error for an expression that went past its limits, which is pointed
+    /// at the sub-expression it came from once that is known
+    pub fn limit(message: impl Into<String>) -> Self {
+        Self::LimitError { message: message.into(), span: None }
+    }
+
+    /// A short name for the kind of error, such as "parse"
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::TokenizeError { .. } => "tokenize",
+            Self::ParseError { .. } => "parse",
+            Self::EvaluationError { .. } => "evaluation",
+            Self::DistributionError { .. } => "distribution",
+            Self::LimitError { .. } => "limit",
+        }
+    }
+
+    /// What went wrong, without the kind of error
+    pub fn message(&self) -> &str {
+        match self {
+            Self::TokenizeError { message, .. }
+                | Self::ParseError { message, .. }
+                | Self::EvaluationError { message, .. }
+                | Self::DistributionError { message, .. }
+                | Self::LimitError { message, .. } => message,
+        }
+    }
+
+    /// The part of the input that caused the error, if it is known
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            Self::TokenizeError { span, .. }
+                | Self::ParseError { span, .. }
+                | Self::EvaluationError { span, .. }
+                | Self::DistributionError { span, .. }
+                | Self::LimitError { span, .. } => *span,
+        }
+    }
+
+    /// Point the error at part of the input, unless it already points somewhere more precise
+    pub fn or_span(mut self, outer: Span) -> Self {
+        match &mut self {
+            Self::TokenizeError { span, .. }
+                | Self::ParseError { span, .. }
+                | Self::EvaluationError { span, .. }
+                | Self::DistributionError { span, .. }
+                | Self::LimitError { span, .. } => {
+                    span.get_or_insert(outer);
+                },
+        }
+        self
+    }
+}
+
+impl std::fmt::Display for RollError {
+    /// Write the error with its kind in front, as in "Parse Error: Expected Number"
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let kind = match self {
+            Self::TokenizeError { .. } => "Tokenize",
+            Self::ParseError { .. } => "Parse",
+            Self::EvaluationError { .. } => "Evaluation",
+            Self::DistributionError { .. } => "Distribution",
+            Self::LimitError { .. } => "Limit",
+        };
+        write!(f, "{} Error: {}", kind, self.message())
+    }
+}
+
+impl std::error::Error for RollError {}
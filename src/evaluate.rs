@@ -1,126 +1,701 @@
 use num_bigint::{BigInt, RandBigInt};
-use num_traits::{Zero, One};
-use crate::parse::{Start, AddLayer, MultLayer, Roll, SubExpression, Number, AddOperator, MultOperator, RollOperator};
+use num_traits::{Zero, One, ToPrimitive, FromPrimitive};
+use rand::Rng;
+use rand::distributions::Uniform;
+use rand_distr::{Binomial, StandardNormal};
+use crate::parse::{Chain, Start, AddLayer, MultLayer, SignLayer, Roll, SubExpression, Number, AddOperator, MultOperator, RollOperator};
+use crate::parse::{Modifier, Comparison, ExplodeOperator, RerollOperator, ComparisonOperator};
+use crate::error::{RollError, Span};
+use crate::limits::{EvalLimits, Budget};
 
-pub fn evaluate(tree: Thunk<Start>) -> Result<BigInt, String> {
-    tree.consume()
+/// The most times a single die may explode, so that dice which always explode still finish
+pub const MAX_EXPLOSIONS: usize = 100;
+
+/// The most times a single die may be rerolled, so that dice which always reroll still finish
+pub const MAX_REROLLS: usize = 100;
+
+/// The most dice that are rolled one at a time when their faces are not being kept
+pub const FAST_ROLL_THRESHOLD: u64 = 1000;
+
+/// The most sides dice may have for the total of a fast roll to be drawn exactly
+pub const MAX_EXACT_SIDES: u64 = 1000;
+
+/// Evaluate an expression tree, which may be evaluated again for a fresh roll
+pub fn evaluate(tree: &Thunk<Start>) -> Result<RollResult, RollError> {
+    evaluate_with_rng(tree, &mut rand::thread_rng())
+}
+
+/// Evaluate an expression tree, drawing every die from the given random number generator
+pub fn evaluate_with_rng<R: Rng>(tree: &Thunk<Start>, rng: &mut R) -> Result<RollResult, RollError> {
+    evaluate_with_limits(tree, rng, &EvalLimits::default())
+}
+
+/// Evaluate an expression tree, drawing every die from the given random number generator,
+/// and stopping with an error if the roll goes past any of the limits
+pub fn evaluate_with_limits<R: Rng>(tree: &Thunk<Start>, rng: &mut R, limits: &EvalLimits) -> Result<RollResult, RollError> {
+    tree.evaluate(rng, &mut Budget::new(limits))
+}
+
+/// Evaluate an expression tree in the same way, except that a roll of more than FAST_ROLL_THRESHOLD dice
+/// that no modifier looks at has only its total drawn. This is much faster, but leaves out the dice
+/// of such a roll and gives a different result for the same seed
+pub fn evaluate_totals_with_limits<R: Rng>(tree: &Thunk<Start>, rng: &mut R, limits: &EvalLimits) -> Result<RollResult, RollError> {
+    tree.evaluate(rng, &mut Budget::new(limits).without_faces())
+}
+
+/// The result of evaluating an expression, along with how it was reached
+#[derive(Debug, Clone, PartialEq)]
+pub struct RollResult {
+    /// The value the expression evaluated to
+    pub total: BigInt,
+    /// The breakdown of how the value was reached
+    pub detail: Detail,
+}
+
+/// The breakdown of a single node of an evaluated expression
+#[derive(Debug, Clone, PartialEq)]
+pub enum Detail {
+    /// A numerical constant
+    Constant,
+    /// An expression wrapped in parentheses
+    Group(Box<RollResult>),
+    /// Two results combined by an addition-level operator
+    Add(Box<RollResult>, AddOperator, Box<RollResult>),
+    /// Two results combined by a multiplication-level operator
+    Mult(Box<RollResult>, MultOperator, Box<RollResult>),
+    /// A result with a plus or minus sign in front of it
+    Sign(AddOperator, Box<RollResult>),
+    /// A pool of dice, along with the results that decided how many dice and how many sides
+    /// (these are absent when a single value was modified rather than rolled)
+    Dice {
+        count: Option<Box<RollResult>>,
+        sides: Option<Box<RollResult>>,
+        dice: Vec<Die>,
+        /// Whether the dice were counted as successes rather than added together
+        counting_successes: bool,
+    },
+}
+
+/// A single die in a pool
+#[derive(Debug, Clone, PartialEq)]
+pub struct Die {
+    /// The face the die landed on, less any penalty from penetrating
+    pub value: BigInt,
+    /// Whether the die was removed by a keep, a drop or a reroll
+    pub dropped: bool,
+    /// Whether the die exploded
+    pub exploded: bool,
+    /// Whether the die counted as a success
+    pub success: bool,
+    /// Whether the die counted as a failure
+    pub failure: bool,
+}
+
+impl Die {
+    /// Create a die that is counted normally
+    fn new(value: BigInt) -> Self {
+        Self {
+            value,
+            dropped: false,
+            exploded: false,
+            success: false,
+            failure: false,
+        }
+    }
+}
+
+impl std::fmt::Display for Die {
+    /// Dropped dice are struck through, exploded dice are marked with a "!",
+    /// and successes and failures are marked with a "✓" and a "✗"
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let exploded = if self.exploded { "!" } else { "" };
+        let outcome = match (self.success, self.failure) {
+            (true, false) => "✓",
+            (false, true) => "✗",
+            _ => "",
+        };
+        if self.dropped {
+            write!(f, "~~{}{}~~", self.value, exploded)
+        } else {
+            write!(f, "{}{}{}", self.value, exploded, outcome)
+        }
+    }
+}
+
+impl std::fmt::Display for RollResult {
+    /// Write out the expression with every die expanded, so that
+    /// "2d6+3" becomes something like "(4 + 2) + 3"
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.detail {
+            Detail::Constant => write!(f, "{}", self.total),
+            Detail::Group(ref inner) => write!(f, "({})", inner),
+            // Chains are written out one link at a time, however long they are
+            Detail::Add(..) | Detail::Mult(..) => {
+                let chain = self.chain();
+                write!(f, "{}", chain[0])?;
+                for link in &chain[1..] {
+                    match link.detail {
+                        Detail::Add(_, op, ref right) => write!(f, " {} {}", op, right)?,
+                        Detail::Mult(_, op, ref right) => write!(f, " {} {}", op, right)?,
+                        _ => unreachable!("only links of the chain are gathered"),
+                    }
+                }
+                Ok(())
+            },
+            Detail::Sign(op, ref signed) => {
+                let symbol = match op {
+                    AddOperator::Add => "+",
+                    AddOperator::Subtract => "-",
+                };
+                write!(f, "{}{}", symbol, signed)
+            },
+            Detail::Dice { ref dice, counting_successes, .. } => {
+                let dice: Vec<String> = dice.iter().map(Die::to_string).collect();
+                // Successes are counted rather than added, so they are shown as a list
+                if counting_successes {
+                    write!(f, "[{}]", dice.join(", "))
+                } else {
+                    write!(f, "({})", dice.join(" + "))
+                }
+            },
+        }
+    }
+}
+
+impl RollResult {
+    /// Create the result of a numerical constant
+    fn constant(total: BigInt) -> Self {
+        Self {
+            total,
+            detail: Detail::Constant,
+        }
+    }
+
+    /// The results along a chain of adding or multiplying, from its first operand to the whole chain
+    pub(crate) fn chain(&self) -> Vec<&Self> {
+        let mut chain = vec![self];
+        let mut last = self;
+        while let (Detail::Add(..), Detail::Add(left, ..)) | (Detail::Mult(..), Detail::Mult(left, ..)) = (&self.detail, &last.detail) {
+            last = left;
+            chain.push(last);
+        }
+        chain.reverse();
+        chain
+    }
+
+    /// Wrap the result of an expression in parentheses
+    fn group(inner: Self) -> Self {
+        Self {
+            total: inner.total.clone(),
+            detail: Detail::Group(Box::new(inner)),
+        }
+    }
 }
 
 /// A trait that represents something that can be evaluated
-/// into a BigInt at some point in time
+/// into a RollResult at some point in time
 pub trait Delayable {
-    fn evaluate(self) -> Result<BigInt, String>;
+    fn evaluate<R: Rng>(&self, rng: &mut R, budget: &mut Budget) -> Result<RollResult, RollError>;
 }
 
 /// Some computation that is stored and evaluated at a
 /// later point
-#[derive(Debug, PartialEq)]
+#[derive(Debug)]
 pub struct Thunk<T: Delayable> {
     /// The thing that can be evaluated at a later point
     delayed: Box<T>,
     /// The result of evaluating the stored expression
-    result: Option<Result<BigInt, String>>,
+    result: Option<Result<RollResult, RollError>>,
+    /// The part of the input the computation was read from
+    span: Span,
 }
 
 impl<T: Delayable + Sized> Thunk<T> {
     /// Create a new Thunk from an item of a known size
+    #[allow(unused_doc_comments)]
     pub fn new(delayed: T) -> Self {
         Self {
             /// Store the computation for later
             delayed: Box::new(delayed),
             /// It is not yet evaluated
             result: None,
+            /// It was not read from anywhere yet
+            span: Span::default(),
         }
     }
-    /// Consume the Thunk and return the result
-    pub fn consume(self) -> Result<BigInt, String> {
-        (*self.delayed).evaluate()
+    /// Record the part of the input the computation was read from
+    pub fn with_span(mut self, span: Span) -> Self {
+        self.span = span;
+        self
+    }
+    /// The part of the input the computation was read from
+    pub fn span(&self) -> Span {
+        self.span
+    }
+    /// Look at the stored computation without evaluating it
+    pub fn delayed(&self) -> &T {
+        &self.delayed
+    }
+    /// Evaluate the stored computation, leaving it in place to be evaluated again
+    pub(crate) fn evaluate<R: Rng>(&self, rng: &mut R, budget: &mut Budget) -> Result<RollResult, RollError> {
+        self.checked(self.delayed.evaluate(rng, budget), budget)
+    }
+    /// Check the result of working out the stored computation, blaming this part of the input for any error
+    pub(crate) fn checked(&self, result: Result<RollResult, RollError>, budget: &Budget) -> Result<RollResult, RollError> {
+        result
+            .and_then(|result| budget.check_value(&result.total).map(|_| result))
+            .map_err(|error| error.or_span(self.span))
+    }
+}
+
+impl<T: Delayable + PartialEq> PartialEq for Thunk<T> {
+    /// Thunks are equal when they hold the same computation, wherever it was read from
+    fn eq(&self, other: &Self) -> bool {
+        self.delayed == other.delayed && self.result == other.result
+    }
+}
+
+impl<T: Delayable + std::fmt::Display> std::fmt::Display for Thunk<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.delayed)
     }
 }
 
 impl Delayable for Start {
-    fn evaluate(self) -> Result<BigInt, String> {
+    fn evaluate<R: Rng>(&self, rng: &mut R, budget: &mut Budget) -> Result<RollResult, RollError> {
         match self {
-            Self::Base(base_thunk) => base_thunk.consume(),
+            Self::Base(base_thunk) => base_thunk.evaluate(rng, budget),
         }
     }
 }
 
 impl Delayable for AddLayer {
-    fn evaluate(self) -> Result<BigInt, String> {
+    fn evaluate<R: Rng>(&self, rng: &mut R, budget: &mut Budget) -> Result<RollResult, RollError> {
+        let (first_thunk, links) = self.links();
+        let mut left = first_thunk.evaluate(rng, budget)?;
+        for (holder, op, right_thunk) in links {
+            let right = right_thunk.evaluate(rng, budget)?;
+            let total = match op {
+                AddOperator::Add => &left.total + &right.total,
+                AddOperator::Subtract => &left.total - &right.total,
+            };
+            left = RollResult {
+                total,
+                detail: Detail::Add(Box::new(left), op, Box::new(right)),
+            };
+            if let Some(holder) = holder {
+                left = holder.checked(Ok(left), budget)?;
+            }
+        }
+        Ok(left)
+    }
+}
+
+impl Delayable for MultLayer {
+    fn evaluate<R: Rng>(&self, rng: &mut R, budget: &mut Budget) -> Result<RollResult, RollError> {
+        let (first_thunk, links) = self.links();
+        let mut left = first_thunk.evaluate(rng, budget)?;
+        for (holder, op, right_thunk) in links {
+            let right = right_thunk.evaluate(rng, budget)?;
+            let total = match op {
+                MultOperator::Multiply => &left.total * &right.total,
+                MultOperator::Divide => {
+                    if right.total.is_zero() {
+                        return Err(RollError::evaluation("Right side of a division was 0").or_span(right_thunk.span()));
+                    }
+                    &left.total / &right.total
+                },
+            };
+            left = RollResult {
+                total,
+                detail: Detail::Mult(Box::new(left), op, Box::new(right)),
+            };
+            if let Some(holder) = holder {
+                left = holder.checked(Ok(left), budget)?;
+            }
+        }
+        Ok(left)
+    }
+}
+
+impl Delayable for SignLayer {
+    fn evaluate<R: Rng>(&self, rng: &mut R, budget: &mut Budget) -> Result<RollResult, RollError> {
         match self {
-            Self::Base(base_thunk) => base_thunk.consume(),
-            Self::Recurse(left_thunk, op, right_thunk) => {
-                let left = left_thunk.consume()?;
-                let right = right_thunk.consume()?;
-                match op {
-                    AddOperator::Add => Ok(left + right),
-                    AddOperator::Subtract => Ok(left - right),
+            Self::Base(base_thunk) => base_thunk.evaluate(rng, budget),
+            Self::Sign(op, signed_thunk) => {
+                let signed = signed_thunk.evaluate(rng, budget)?;
+                let total = match op {
+                    AddOperator::Add => signed.total.clone(),
+                    AddOperator::Subtract => -&signed.total,
+                };
+                Ok(RollResult {
+                    total,
+                    detail: Detail::Sign(*op, Box::new(signed)),
+                })
+            },
+        }
+    }
+}
+
+impl Delayable for Roll {
+    fn evaluate<R: Rng>(&self, rng: &mut R, budget: &mut Budget) -> Result<RollResult, RollError> {
+        match self {
+            // A lone sub-expression is not a pool of dice
+            Self::Base(base_thunk) => base_thunk.evaluate(rng, budget),
+            // Nothing looks at the faces of a roll that is not modified, so when they are not
+            // being kept, only the total of a roll of many dice is drawn
+            Self::Recurse(left_thunk, RollOperator::D, right_thunk) if !budget.keeps_faces() => {
+                let (left, right) = roll_sides(left_thunk, right_thunk, rng, budget)?;
+                match left.total.to_u64() {
+                    Some(count) if count <= FAST_ROLL_THRESHOLD => Ok(roll_each(left_thunk, left, right, rng, budget)?.into_result()),
+                    _ => Ok(RollResult {
+                        total: sum_dice(rng, &left.total, &right.total),
+                        detail: Detail::Dice {
+                            count: Some(Box::new(left)),
+                            sides: Some(Box::new(right)),
+                            dice: Vec::new(),
+                            counting_successes: false,
+                        },
+                    }),
                 }
             },
+            _ => Ok(self.pool(rng, budget)?.into_result()),
         }
     }
 }
 
-impl Delayable for MultLayer {
-    fn evaluate(self) -> Result<BigInt, String> {
+/// The individual dice produced by a roll, before they are added together
+struct Pool {
+    /// Every die in the pool, including those that are no longer counted
+    dice: Vec<Die>,
+    /// The result that decided how many dice were rolled, if they came from a roll
+    count: Option<RollResult>,
+    /// The result that decided how many sides the dice have, if they came from a roll
+    sides: Option<RollResult>,
+    /// The target a die must meet to count as a success, if successes are being counted
+    successes: Option<Target>,
+    /// The target a die must meet to count as a failure, if failures are being counted
+    failures: Option<Target>,
+}
+
+impl Pool {
+    /// Create a pool that is not counting successes
+    fn new(dice: Vec<Die>, count: Option<RollResult>, sides: Option<RollResult>) -> Self {
+        Self {
+            dice,
+            count,
+            sides,
+            successes: None,
+            failures: None,
+        }
+    }
+
+    /// The number of sides on the dice, if they came from a roll
+    fn sides(&self) -> Option<BigInt> {
+        self.sides.as_ref().map(|sides| sides.total.clone())
+    }
+
+    /// Add together every die that is still being counted, or
+    /// count the successes less the failures if there is a target
+    fn total(&self) -> BigInt {
+        let counted = self.dice.iter().filter(|die| !die.dropped);
+        match self.successes {
+            Some(ref successes) => {
+                let count = |target: &Target| counted.clone().filter(|die| target.matches(&die.value)).count();
+                let failures = self.failures.as_ref().map_or(0, count);
+                BigInt::from(count(successes)) - failures
+            },
+            None => counted.map(|die| &die.value).sum(),
+        }
+    }
+
+    /// Turn the pool into the result of its roll
+    fn into_result(mut self) -> RollResult {
+        let total = self.total();
+        // Mark which of the counted dice were successes and failures
+        for die in self.dice.iter_mut().filter(|die| !die.dropped) {
+            die.success = self.successes.as_ref().is_some_and(|target| target.matches(&die.value));
+            die.failure = self.failures.as_ref().is_some_and(|target| target.matches(&die.value));
+        }
+        RollResult {
+            total,
+            detail: Detail::Dice {
+                count: self.count.map(Box::new),
+                sides: self.sides.map(Box::new),
+                dice: self.dice,
+                counting_successes: self.successes.is_some(),
+            },
+        }
+    }
+}
+
+impl Thunk<Roll> {
+    /// Evaluate the Thunk into the dice that make up the result
+    fn pool<R: Rng>(&self, rng: &mut R, budget: &mut Budget) -> Result<Pool, RollError> {
+        self.delayed.pool(rng, budget).map_err(|error| error.or_span(self.span))
+    }
+}
+
+impl Roll {
+    fn pool<R: Rng>(&self, rng: &mut R, budget: &mut Budget) -> Result<Pool, RollError> {
         match self {
-            Self::Base(base_thunk) => base_thunk.consume(),
+            Self::Base(base_thunk) => {
+                let base = base_thunk.evaluate(rng, budget)?;
+                Ok(Pool::new(vec![Die::new(base.total)], None, None))
+            },
+            Self::Recurse(left_thunk, RollOperator::D, right_thunk) => {
+                let (left, right) = roll_sides(left_thunk, right_thunk, rng, budget)?;
+                roll_each(left_thunk, left, right, rng, budget)
+            },
             Self::Recurse(left_thunk, op, right_thunk) => {
-                let left = left_thunk.consume()?;
-                let right = right_thunk.consume()?;
+                let mut pool = left_thunk.pool(rng, budget)?;
+                let right = right_thunk.evaluate(rng, budget)?.total;
+                if right < BigInt::zero() {
+                    return Err(RollError::evaluation(format!("Right side of a keep or drop was less than 0, found {}", right)).or_span(right_thunk.span()));
+                }
+                // Only the dice that are still counted can be kept or dropped
+                let mut candidates: Vec<usize> = (0..pool.dice.len()).filter(|&index| !pool.dice[index].dropped).collect();
+                // Any count too large for a usize is certainly larger than the pool
+                let count = right.to_usize().unwrap_or(usize::MAX).min(candidates.len());
+                // Sort so that the dice to keep are at the front
                 match op {
-                    MultOperator::Multiply => Ok(left * right),
-                    MultOperator::Divide => Ok(left / right),
+                    RollOperator::KeepHighest | RollOperator::DropLowest => candidates.sort_by(|&a, &b| pool.dice[b].value.cmp(&pool.dice[a].value)),
+                    RollOperator::KeepLowest | RollOperator::DropHighest => candidates.sort_by(|&a, &b| pool.dice[a].value.cmp(&pool.dice[b].value)),
+                    RollOperator::D => unreachable!(),
+                }
+                // Work out how many of the front dice survive
+                let kept = match op {
+                    RollOperator::KeepHighest | RollOperator::KeepLowest => count,
+                    RollOperator::DropHighest | RollOperator::DropLowest => candidates.len() - count,
+                    RollOperator::D => unreachable!(),
+                };
+                for &index in &candidates[kept..] {
+                    pool.dice[index].dropped = true;
                 }
+                Ok(pool)
             },
+            Self::Modify(left_thunk, modifier) => modifier.apply(left_thunk.pool(rng, budget)?, rng, budget),
         }
     }
 }
 
-impl Delayable for Roll {
-    fn evaluate(self) -> Result<BigInt, String> {
+/// Evaluate how many dice a roll has and how many sides they have, and take the dice from the budget
+fn roll_sides<R: Rng>(left_thunk: &Thunk<Roll>, right_thunk: &Thunk<SubExpression>, rng: &mut R, budget: &mut Budget) -> Result<(RollResult, RollResult), RollError> {
+    let left = left_thunk.evaluate(rng, budget)?;
+    let right = right_thunk.evaluate(rng, budget)?;
+    if left.total < BigInt::zero() {
+        return Err(RollError::evaluation(format!("Left side of a roll was less than 0, found {}", left.total)).or_span(left_thunk.span()));
+    }
+    if right.total < BigInt::one() {
+        return Err(RollError::evaluation(format!("Right side of a roll was less than 1, found {}", right.total)).or_span(right_thunk.span()));
+    }
+    budget.take_dice(&left.total)?;
+    Ok((left, right))
+}
+
+/// Roll every die of a roll one at a time, given how many dice there are and how many sides they have
+fn roll_each<R: Rng>(left_thunk: &Thunk<Roll>, left: RollResult, right: RollResult, rng: &mut R, budget: &Budget) -> Result<Pool, RollError> {
+    // No pool with more dice than this could ever be held
+    let count = left.total.to_u64().ok_or_else(|| RollError::evaluation(
+        format!("Left side of a roll was too large to roll each die, found {}", left.total)
+    ).or_span(left_thunk.span()))?;
+    let mut dice = Vec::new();
+    for _ in 0..count {
+        dice.push(Die::new(roll_die(rng, &right.total, budget)?));
+    }
+    Ok(Pool::new(dice, Some(left), Some(right)))
+}
+
+/// Roll a single die with the given number of sides, which must already have been taken from the budget
+fn roll_die<R: Rng>(rng: &mut R, sides: &BigInt, budget: &Budget) -> Result<BigInt, RollError> {
+    budget.check_time()?;
+    // Machine integers are much faster to draw, and almost every die has few enough sides for one
+    Ok(match sides.to_u64() {
+        Some(sides) => BigInt::from(rng.sample(Uniform::new_inclusive(1, sides))),
+        None => rng.gen_bigint_range(&BigInt::one(), &(sides + 1)),
+    })
+}
+
+/// Draw the total of many dice without rolling each one, exactly when the dice have few enough
+/// sides, or from the normal distribution that the total comes close to otherwise
+fn sum_dice<R: Rng>(rng: &mut R, count: &BigInt, sides: &BigInt) -> BigInt {
+    if let (Some(count), Some(sides)) = (count.to_u64(), sides.to_u64()) {
+        if sides <= MAX_EXACT_SIDES {
+            // Share the dice out between the faces, one face at a time
+            let mut total = BigInt::zero();
+            let mut dice_left = count;
+            for face in 1..sides {
+                // Each die that is left is as likely to land on this face as on any face after it
+                let landed = rng.sample(Binomial::new(dice_left, 1.0 / (sides - face + 1) as f64).unwrap());
+                total += BigInt::from(face) * landed;
+                dice_left -= landed;
+            }
+            return total + BigInt::from(sides) * dice_left;
+        }
+    }
+    let (count_f, sides_f) = (count.to_f64().unwrap_or(f64::INFINITY), sides.to_f64().unwrap_or(f64::INFINITY));
+    let deviation = (count_f * (sides_f * sides_f - 1.0) / 12.0).sqrt();
+    let z: f64 = rng.sample(StandardNormal);
+    // Work in twice the total, so that the mean stays a whole number
+    let offset = BigInt::from_f64((2.0 * z * deviation).round()).unwrap_or_default();
+    let total: BigInt = (count * (sides + 1u32) + offset) / 2;
+    // The normal distribution has tails that no roll could reach
+    total.max(count.clone()).min(count * sides)
+}
+
+impl Modifier {
+    /// Change the dice in a pool according to the modifier
+    fn apply<R: Rng>(&self, mut pool: Pool, rng: &mut R, budget: &mut Budget) -> Result<Pool, RollError> {
         match self {
-            Self::Base(base_thunk) => base_thunk.consume(),
-            Self::Recurse(left_thunk, op, right_thunk) => {
-                let left = left_thunk.consume()?;
-                let right = right_thunk.consume()?;
-                match op {
-                    RollOperator::D => {
-                        if left < BigInt::zero() {
-                            Err(format!("Evaluation Error: Left side of a roll was less than 0, found {}", left))
-                        } else {
-                            let mut rng = rand::thread_rng();
-                            let mut sum = BigInt::zero();
-                            let mut dice_left = left;
-                            let upper_bound_exclusive = right + 1;
-                            while dice_left > BigInt::zero() {
-                                sum += rng.gen_bigint_range(&BigInt::one(), &upper_bound_exclusive);
-                                dice_left -= 1;
+            Self::Explode(op, comparison) => {
+                let sides = match pool.sides() {
+                    Some(sides) => sides,
+                    None => return Err(RollError::evaluation("Only dice can explode, found a constant")),
+                };
+                // By default, dice explode on their highest face
+                let trigger = match comparison {
+                    Some(comparison) => comparison.target(rng, budget)?,
+                    None => Target { op: ComparisonOperator::Equal, value: sides.clone() },
+                };
+                let mut dice = Vec::new();
+                for mut die in pool.dice {
+                    // Dice that are no longer counted cannot explode
+                    if die.dropped || !trigger.matches(&die.value) {
+                        dice.push(die);
+                        continue;
+                    }
+                    die.exploded = true;
+                    let mut current = die.value.clone();
+                    let mut explosions = Vec::new();
+                    while trigger.matches(&current) && explosions.len() < MAX_EXPLOSIONS {
+                        budget.take_dice(&BigInt::one())?;
+                        current = roll_die(rng, &sides, budget)?;
+                        explosions.push(current.clone());
+                    }
+                    match op {
+                        ExplodeOperator::Explode | ExplodeOperator::Penetrate => {
+                            let last = explosions.len() - 1;
+                            dice.push(die);
+                            for (index, value) in explosions.into_iter().enumerate() {
+                                // Every explosion but the last went on to explode again, judged on the face it shows
+                                let exploded = index != last || trigger.matches(&value);
+                                let mut new_die = match op {
+                                    // Each penetrating die is worth one less than it shows
+                                    ExplodeOperator::Penetrate => Die::new(value - 1),
+                                    _ => Die::new(value),
+                                };
+                                new_die.exploded = exploded;
+                                dice.push(new_die);
                             }
-                            Ok(sum)
-                        }
+                        },
+                        ExplodeOperator::Compound => {
+                            die.value += explosions.iter().sum::<BigInt>();
+                            dice.push(die);
+                        },
+                    }
+                }
+                pool.dice = dice;
+                Ok(pool)
+            },
+            Self::Reroll(op, comparison) => {
+                let sides = match pool.sides() {
+                    Some(sides) => sides,
+                    None => return Err(RollError::evaluation("Only dice can be rerolled, found a constant")),
+                };
+                // By default, dice are rerolled on their lowest face
+                let trigger = match comparison {
+                    Some(comparison) => comparison.target(rng, budget)?,
+                    None => Target { op: ComparisonOperator::Equal, value: BigInt::one() },
+                };
+                let limit = match op {
+                    RerollOperator::Reroll => MAX_REROLLS,
+                    RerollOperator::RerollOnce => 1,
+                };
+                let mut dice = Vec::new();
+                for mut die in pool.dice {
+                    // Dice that are no longer counted are not rerolled
+                    let mut rerolls = if die.dropped { limit } else { 0 };
+                    // Each die that is replaced stays in the pool, but is no longer counted
+                    while trigger.matches(&die.value) && rerolls < limit {
+                        budget.take_dice(&BigInt::one())?;
+                        let replacement = Die::new(roll_die(rng, &sides, budget)?);
+                        die.dropped = true;
+                        dice.push(die);
+                        die = replacement;
+                        rerolls += 1;
                     }
+                    dice.push(die);
+                }
+                pool.dice = dice;
+                Ok(pool)
+            },
+            Self::Success(comparison) => {
+                pool.successes = Some(comparison.target(rng, budget)?);
+                Ok(pool)
+            },
+            Self::Failure(comparison) => {
+                if pool.successes.is_none() {
+                    return Err(RollError::evaluation("Failures can only be counted after a success target"));
                 }
+                pool.failures = Some(comparison.target(rng, budget)?);
+                Ok(pool)
             },
         }
     }
 }
 
+/// A comparison that has had its target evaluated
+#[derive(Clone)]
+pub struct Target {
+    /// How each die is compared against the value
+    pub op: ComparisonOperator,
+    /// The value each die is compared against
+    pub value: BigInt,
+}
+
+impl Target {
+    /// Check whether a die satisfies the comparison
+    pub fn matches(&self, die: &BigInt) -> bool {
+        match self.op {
+            ComparisonOperator::Equal => *die == self.value,
+            ComparisonOperator::Less => *die < self.value,
+            ComparisonOperator::LessEqual => *die <= self.value,
+            ComparisonOperator::Greater => *die > self.value,
+            ComparisonOperator::GreaterEqual => *die >= self.value,
+        }
+    }
+}
+
+impl Comparison {
+    /// Evaluate the target of the Comparison
+    fn target<R: Rng>(&self, rng: &mut R, budget: &mut Budget) -> Result<Target, RollError> {
+        let Self::Compare(op, value_thunk) = self;
+        Ok(Target { op: *op, value: value_thunk.evaluate(rng, budget)?.total })
+    }
+}
+
 impl Delayable for SubExpression {
-    fn evaluate(self) -> Result<BigInt, String> {
+    fn evaluate<R: Rng>(&self, rng: &mut R, budget: &mut Budget) -> Result<RollResult, RollError> {
         match self {
-            Self::Base(number_thunk) => number_thunk.consume(),
-            Self::Recurse(start_thunk) => start_thunk.consume(),
+            Self::Base(number_thunk) => number_thunk.evaluate(rng, budget),
+            Self::Recurse(start_thunk) => Ok(RollResult::group(start_thunk.evaluate(rng, budget)?)),
         }
     }
 }
 
 impl Delayable for Number {
-    fn evaluate(self) -> Result<BigInt, String> {
+    fn evaluate<R: Rng>(&self, _rng: &mut R, _budget: &mut Budget) -> Result<RollResult, RollError> {
+        Ok(RollResult::constant(self.value()?))
+    }
+}
+
+impl Number {
+    /// Read the value of the number out of its string representation
+    #[allow(clippy::while_let_on_iterator, clippy::assign_op_pattern)]
+    pub fn value(&self) -> Result<BigInt, RollError> {
         let Self::StringRepresentation(string_rep) = self;
         if string_rep.is_empty() {
-            Err("Evaluation Error: Found empty number".to_string())
+            Err(RollError::evaluation("Found empty number"))
         } else {
             let mut digits = string_rep.chars().peekable();
             let mut value = if digits.peek() == Some(&'%') {
@@ -142,10 +717,213 @@ impl Delayable for Number {
                     '8' => value = (value * 10) + 8,
                     '9' => value = (value * 10) + 9,
                     '%' => value = value * 100,
-                    _ => return Err(format!("Evaluation Error: Expected '0'-'9' or '%', found {}", digit)),
+                    _ => return Err(RollError::evaluation(format!("Expected '0'-'9' or '%', found {}", digit))),
                 }
             }
             Ok(value)
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use num_bigint::BigInt;
+    use crate::tokenize::tokenize;
+    use crate::parse::parse;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+    use crate::evaluate::{evaluate, evaluate_with_rng, evaluate_with_limits, evaluate_totals_with_limits, RollResult, Detail, Die};
+    use crate::limits::{EvalLimits, DEFAULT_MAX_DEPTH};
+    use crate::distribution::distribution_with_limits;
+    use crate::error::Span;
+
+    /// Evaluate an expression from its text
+    fn roll(input: &str) -> RollResult {
+        evaluate(&parse(tokenize(input.to_string())).unwrap()[0]).unwrap()
+    }
+
+    #[test]
+    fn dropped_dice_stay_in_order() {
+        let result = roll("3d1dh1");
+        assert_eq!(BigInt::from(2), result.total);
+        match result.detail {
+            Detail::Dice { count, sides, dice, .. } => {
+                assert_eq!(Some(BigInt::from(3)), count.map(|count| count.total));
+                assert_eq!(Some(BigInt::from(1)), sides.map(|sides| sides.total));
+                assert_eq!(
+                    vec!["1", "1", "~~1~~"],
+                    dice.iter().map(Die::to_string).collect::<Vec<_>>()
+                );
+            },
+            detail => panic!("Expected dice, found {:?}", detail),
+        }
+    }
+
+    #[test]
+    fn penetrated_dice_explode_on_their_face() {
+        for seed in 0..20 {
+            let tree = parse(tokenize("1d3!p<3".to_string())).unwrap().remove(0);
+            let result = evaluate_with_rng(&tree, &mut StdRng::seed_from_u64(seed)).unwrap();
+            match result.detail {
+                Detail::Dice { dice, .. } => {
+                    // The chain stops on a 3, which is counted as a 2 but did not explode
+                    let last = dice.last().unwrap();
+                    if dice.len() > 1 {
+                        assert_eq!((BigInt::from(2), false), (last.value.clone(), last.exploded));
+                    }
+                },
+                detail => panic!("Expected dice, found {:?}", detail),
+            }
+        }
+    }
+
+    #[test]
+    fn subtotals_of_each_layer() {
+        let result = roll("2*(1d1+3)");
+        assert_eq!(BigInt::from(8), result.total);
+        match result.detail {
+            Detail::Mult(left, _, right) => {
+                assert_eq!(Detail::Constant, left.detail);
+                assert_eq!(BigInt::from(4), right.total);
+                assert!(matches!(right.detail, Detail::Group(_)));
+            },
+            detail => panic!("Expected multiplication, found {:?}", detail),
+        }
+    }
+
+    #[test]
+    fn same_seed_same_dice() {
+        let tree = parse(tokenize("10d20!r1".to_string())).unwrap().remove(0);
+        let seeded = |seed| evaluate_with_rng(
+            &tree,
+            &mut StdRng::seed_from_u64(seed),
+        ).unwrap();
+        assert_eq!(seeded(42), seeded(42));
+    }
+
+    #[test]
+    fn expanded_expression() {
+        assert_eq!("(1 + 1) + 3", roll("2d1+3").to_string());
+        assert_eq!("2 * ((1 + ~~1~~) - 1)", roll("2*(2d1kl1-1)").to_string());
+        assert_eq!("[1✓, 1✓]", roll("2d1>=1").to_string());
+        assert_eq!("-(1 + 1) + (-3)", roll("-2d1 + (-3)").to_string());
+        assert_eq!(BigInt::from(-5), roll("-2d1 + (-3)").total);
+        assert_eq!(BigInt::from(6), roll("2 * -+-3").total);
+    }
+
+    #[test]
+    fn error_points_at_sub_expression() {
+        let error = evaluate(&parse(tokenize("1 + (0-2)d6".to_string())).unwrap()[0]).unwrap_err();
+        assert_eq!(Some(Span::new(4, 9)), error.span());
+        assert_eq!("Evaluation Error: Left side of a roll was less than 0, found -2", error.to_string());
+        let error = evaluate(&parse(tokenize("2 + 3!".to_string())).unwrap()[0]).unwrap_err();
+        assert_eq!(Some(Span::new(4, 6)), error.span());
+        let error = evaluate(&parse(tokenize("1 + 100000000000000000000d6".to_string())).unwrap()[0]).unwrap_err();
+        assert_eq!(Some(Span::new(4, 25)), error.span());
+    }
+
+    #[test]
+    fn invalid_arithmetic_and_dice() {
+        let error = |input: &str| evaluate(&parse(tokenize(input.to_string())).unwrap()[0]).unwrap_err();
+        assert_eq!("Evaluation Error: Right side of a division was 0", error("6 / (1d1 - 1)").to_string());
+        assert_eq!(Some(Span::new(4, 13)), error("6 / (1d1 - 1)").span());
+        assert_eq!("Evaluation Error: Right side of a roll was less than 1, found 0", error("1d0").to_string());
+        assert_eq!(Some(Span::new(2, 3)), error("1d0").span());
+        assert_eq!(Some(Span::new(6, 11)), error("2 + 1d(0-3)").span());
+    }
+
+    #[test]
+    fn fast_rolls_of_many_dice() {
+        let limits = EvalLimits::default();
+        let roll_fast = |input: &str| evaluate_totals_with_limits(
+            &parse(tokenize(input.to_string())).unwrap()[0],
+            &mut StdRng::seed_from_u64(3),
+            &limits,
+        ).unwrap();
+        assert_eq!(BigInt::from(1_000_000_000_000u64), roll_fast("1000000000000d1").total);
+        let exact = roll_fast("1000000000d6").total;
+        assert!(exact >= BigInt::from(3_499_000_000u64) && exact <= BigInt::from(3_501_000_000u64), "Found {}", exact);
+        let approximate = roll_fast("1000000d1000000").total;
+        assert!(approximate >= BigInt::from(499_000_000_000u64) && approximate <= BigInt::from(501_000_000_000u64), "Found {}", approximate);
+        assert!(matches!(roll_fast("5000d6").detail, Detail::Dice { ref dice, .. } if dice.is_empty()));
+        // Few enough dice are still rolled one at a time, just as when their faces are kept
+        let seeded = |input: &str| parse(tokenize(input.to_string())).unwrap().remove(0);
+        assert_eq!(
+            evaluate_with_limits(&seeded("(1d4)d6"), &mut StdRng::seed_from_u64(9), &limits).unwrap().total,
+            evaluate_totals_with_limits(&seeded("(1d4)d6"), &mut StdRng::seed_from_u64(9), &limits).unwrap().total
+        );
+    }
+
+    #[test]
+    fn long_chains_within_the_default_limit() {
+        // Chains are worked through one link at a time, so the longest ones allowed fit in a test's stack
+        for (op, total) in [("+", 1601), ("*", 1)] {
+            let input = vec!["1"; 1601].join(op);
+            let tree = parse(tokenize(input.clone())).unwrap().remove(0);
+            let result = evaluate(&tree).unwrap();
+            assert_eq!(BigInt::from(total), result.total);
+            assert_eq!(input.replace(op, &format!(" {} ", op)), tree.to_string());
+            assert_eq!(tree.to_string(), result.to_string());
+            assert!(crate::json::result_json("", &tree, 1, &result).to_string().contains("\"left\""));
+            assert_eq!(&BigInt::from(total), distribution_with_limits(&tree, &EvalLimits::default()).unwrap().min());
+        }
+    }
+
+    /// The pieces that generated inputs are made from, kept small so that every roll finishes quickly
+    const PIECES: &[&str] = &[
+        "0", "1", "2", "3", "6", "10", "d", "kh", "kl", "dh", "dl", "!", "!!", "!p", "r", "ro", "f",
+        "<", "<=", ">", ">=", "=", "+", "-", "*", "/", "(", ")", ",", "x", "#",
+    ];
+
+    /// The operators that generated chains are made from, each of which is quick to roll with 1 on either side
+    const CHAIN_OPERATORS: &[&str] = &["+", "-", "*", "/", "d", "kh", "dl"];
+
+    // These only try inputs of the shapes generated here, so they can find a panic but not rule one out
+    proptest::proptest! {
+        #[test]
+        fn any_text_reads_without_panicking(input in "\\PC{0,40}") {
+            let _ = parse(tokenize(input));
+        }
+
+        #[test]
+        fn any_pieces_roll_without_panicking(pieces in proptest::collection::vec(proptest::sample::select(PIECES), 0..12)) {
+            // Spaces keep numbers apart, so that no roll has too many dice to finish
+            if let Ok(trees) = parse(tokenize(pieces.join(" "))) {
+                for tree in &trees {
+                    let _ = evaluate(tree);
+                }
+            }
+        }
+
+        #[test]
+        fn any_pieces_weigh_without_panicking(pieces in proptest::collection::vec(proptest::sample::select(PIECES), 0..12)) {
+            // Weighing is cut short rather than left to run, since a few pieces can have a great many outcomes
+            let limits = EvalLimits { max_time: Some(std::time::Duration::from_millis(100)), ..EvalLimits::default() };
+            if let Ok(trees) = parse(tokenize(pieces.join(" "))) {
+                for tree in &trees {
+                    let _ = distribution_with_limits(tree, &limits);
+                }
+            }
+        }
+
+        #[test]
+        fn long_chains_stop_at_the_depth_limit(
+            depth in 0..120usize,
+            operators in proptest::collection::vec(proptest::sample::select(CHAIN_OPERATORS), 0..3000),
+        ) {
+            // Chains either side of the limit, inside parentheses that may take up some of it themselves
+            let chain: String = operators.iter().map(|op| format!("{}1", op)).collect();
+            let input = format!("{}1{}{}", "(".repeat(depth), chain, ")".repeat(depth));
+            match parse(tokenize(input)) {
+                Ok(trees) => {
+                    proptest::prop_assert!(depth <= DEFAULT_MAX_DEPTH);
+                    for tree in &trees {
+                        let _ = evaluate(tree);
+                        let _ = tree.to_string();
+                    }
+                },
+                Err(error) => proptest::prop_assert_eq!("limit", error.kind()),
+            }
+        }
+    }
+}
@@ -0,0 +1,44 @@
+use num_bigint::BigInt;
+
+/// Draw a horizontal bar for each outcome, with the most likely outcome's bar
+/// taking up the whole width and every other bar scaled to match
+pub fn histogram<'a>(outcomes: impl IntoIterator<Item = (&'a BigInt, f64)>, width: usize) -> String {
+    let outcomes: Vec<(String, f64)> = outcomes.into_iter()
+        .map(|(outcome, probability)| (outcome.to_string(), probability))
+        .collect();
+    // Line the bars up after the widest outcome
+    let label_width = outcomes.iter().map(|(label, _)| label.len()).max().unwrap_or(0);
+    let highest = outcomes.iter().map(|&(_, probability)| probability).fold(0.0, f64::max);
+    let mut drawn = String::new();
+    for (label, probability) in outcomes {
+        let length = if highest > 0.0 {
+            (probability / highest * width as f64).round() as usize
+        } else {
+            0
+        };
+        drawn.push_str(&format!(
+            "{:>label_width$} | {:<width$} {:>7.3}%\n",
+            label,
+            "#".repeat(length),
+            100.0 * probability,
+            label_width = label_width,
+            width = width,
+        ));
+    }
+    drawn
+}
+
+#[cfg(test)]
+mod tests {
+    use num_bigint::BigInt;
+    use crate::histogram::histogram;
+
+    #[test]
+    fn bars_scale_to_most_likely() {
+        let outcomes = [BigInt::from(1), BigInt::from(10)];
+        assert_eq!(
+            " 1 | ##    25.000%\n10 | ####  50.000%\n",
+            histogram(outcomes.iter().zip([0.25, 0.5]), 4)
+        );
+    }
+}
@@ -0,0 +1,146 @@
+use num_bigint::BigInt;
+use num_traits::ToPrimitive;
+use serde_json::{json, Value};
+use crate::parse::{Start, AddOperator, MultOperator};
+use crate::evaluate::{Thunk, RollResult, Detail, Die};
+use crate::error::RollError;
+
+/// Describe the roll of a single expression, along with how its total was reached
+pub fn result_json(label: &str, tree: &Thunk<Start>, repetition: usize, result: &RollResult) -> Value {
+    json!({
+        "label": label,
+        "expression": tree.to_string(),
+        "repetition": repetition,
+        "total": number_json(&result.total),
+        "breakdown": detail_json(result),
+    })
+}
+
+/// Describe an error, along with the part of the input that caused it
+pub fn error_json(error: &RollError) -> Value {
+    json!({
+        "kind": error.kind(),
+        "message": error.message(),
+        "span": error.span().map(|span| json!({ "start": span.start, "end": span.end })),
+    })
+}
+
+/// Write a number as a JSON number, or as a string if it is too large for one
+fn number_json(number: &BigInt) -> Value {
+    match number.to_i64() {
+        Some(number) => json!(number),
+        None => json!(number.to_string()),
+    }
+}
+
+/// Describe a node of an evaluated expression, along with every node beneath it
+fn detail_json(result: &RollResult) -> Value {
+    let total = number_json(&result.total);
+    match result.detail {
+        Detail::Constant => json!({ "kind": "constant", "total": total }),
+        Detail::Group(ref inner) => json!({ "kind": "group", "total": total, "inner": detail_json(inner) }),
+        // Chains are described from their first link outwards, so a long chain needs no more room than a short one
+        Detail::Add(..) | Detail::Mult(..) => {
+            let chain = result.chain();
+            let mut left = detail_json(chain[0]);
+            for link in &chain[1..] {
+                let kind = match link.detail {
+                    Detail::Add(_, AddOperator::Add, _) => "add",
+                    Detail::Add(_, AddOperator::Subtract, _) => "subtract",
+                    Detail::Mult(_, MultOperator::Multiply, _) => "multiply",
+                    Detail::Mult(_, MultOperator::Divide, _) => "divide",
+                    _ => unreachable!("only links of the chain are gathered"),
+                };
+                let right = match link.detail {
+                    Detail::Add(_, _, ref right) | Detail::Mult(_, _, ref right) => right,
+                    _ => unreachable!("only links of the chain are gathered"),
+                };
+                left = json!({
+                    "kind": kind,
+                    "total": number_json(&link.total),
+                    "left": left,
+                    "right": detail_json(right),
+                });
+            }
+            left
+        },
+        Detail::Sign(op, ref signed) => json!({
+            "kind": match op {
+                AddOperator::Add => "plus",
+                AddOperator::Subtract => "minus",
+            },
+            "total": total,
+            "inner": detail_json(signed),
+        }),
+        Detail::Dice { ref count, ref sides, ref dice, counting_successes } => json!({
+            "kind": "dice",
+            "total": total,
+            "count": count.as_ref().map(|count| detail_json(count)),
+            "sides": sides.as_ref().map(|sides| detail_json(sides)),
+            "counting_successes": counting_successes,
+            "dice": dice.iter().map(die_json).collect::<Vec<_>>(),
+        }),
+    }
+}
+
+/// Describe a single die in a pool
+fn die_json(die: &Die) -> Value {
+    json!({
+        "value": number_json(&die.value),
+        "dropped": die.dropped,
+        "exploded": die.exploded,
+        "success": die.success,
+        "failure": die.failure,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use crate::tokenize::tokenize;
+    use crate::parse::parse;
+    use crate::evaluate::evaluate;
+    use crate::json::{result_json, error_json};
+
+    #[test]
+    fn roll_breakdown() {
+        let tree = parse(tokenize("2d1kh1+3".to_string())).unwrap().remove(0);
+        let result = evaluate(&tree).unwrap();
+        let die = |dropped| json!({ "value": 1, "dropped": dropped, "exploded": false, "success": false, "failure": false });
+        assert_eq!(
+            json!({
+                "label": "2d1kh1+3",
+                "expression": "2d1kh1 + 3",
+                "repetition": 1,
+                "total": 4,
+                "breakdown": {
+                    "kind": "add",
+                    "total": 4,
+                    "left": {
+                        "kind": "dice",
+                        "total": 1,
+                        "count": { "kind": "constant", "total": 2 },
+                        "sides": { "kind": "constant", "total": 1 },
+                        "counting_successes": false,
+                        "dice": [die(false), die(true)],
+                    },
+                    "right": { "kind": "constant", "total": 3 },
+                },
+            }),
+            result_json("2d1kh1+3", &tree, 1, &result)
+        );
+    }
+
+    #[test]
+    fn error_kind_and_span() {
+        let error = parse(tokenize("2d6+(".to_string())).unwrap_err();
+        assert_eq!(
+            json!({
+                "kind": "parse",
+                "message": "Expected nested start or \"(\", found end of stream",
+                "span": { "start": 5, "end": 5 },
+            }),
+            error_json(&error)
+        );
+    }
+}
@@ -1,7 +1,25 @@
+mod error;
 mod tokenize;
 mod parse;
 mod evaluate;
+mod distribution;
+mod histogram;
+mod simulate;
+mod session;
+mod json;
+mod diagnostic;
+mod limits;
 
-pub use tokenize::tokenize;
-pub use parse::parse;
-pub use evaluate::evaluate;
+pub use error::{RollError, Span};
+pub use tokenize::{tokenize, Token};
+pub use parse::{parse, parse_with_limits};
+pub use parse::{Start, AddOperator, MultOperator};
+pub use evaluate::{evaluate, evaluate_with_rng, evaluate_with_limits, evaluate_totals_with_limits};
+pub use evaluate::{Thunk, RollResult, Detail, Die};
+pub use distribution::{distribution, distribution_with_limits, Distribution};
+pub use histogram::histogram;
+pub use simulate::{simulate, Simulation};
+pub use session::Session;
+pub use json::{result_json, error_json};
+pub use diagnostic::diagnostic;
+pub use limits::{EvalLimits, DEFAULT_MAX_DEPTH};
@@ -0,0 +1,126 @@
+use std::time::{Duration, Instant};
+use num_bigint::BigInt;
+use crate::error::RollError;
+
+/// How deeply an expression may be nested unless told otherwise, so that reading an expression cannot overflow the stack
+pub const DEFAULT_MAX_DEPTH: usize = 100;
+
+/// Bounds on how much work reading and rolling a single expression may do
+#[derive(Debug, Clone, PartialEq)]
+pub struct EvalLimits {
+    /// The most dice a roll may roll, counting every explosion and reroll
+    pub max_dice: Option<BigInt>,
+    /// The most deeply parentheses, signs and chains of operators may be nested, where every 16 links of
+    /// a chain of adding or multiplying count as one level
+    pub max_depth: Option<usize>,
+    /// The most bits the value of any part of a roll may take up
+    pub max_bits: Option<u64>,
+    /// The longest a roll may take
+    pub max_time: Option<Duration>,
+}
+
+impl Default for EvalLimits {
+    /// Only the depth is limited, and every roll may take as long as it needs
+    fn default() -> Self {
+        Self {
+            max_dice: None,
+            max_depth: Some(DEFAULT_MAX_DEPTH),
+            max_bits: None,
+            max_time: None,
+        }
+    }
+}
+
+impl EvalLimits {
+    /// Limits that never stop anything
+    pub fn unlimited() -> Self {
+        Self {
+            max_dice: None,
+            max_depth: None,
+            max_bits: None,
+            max_time: None,
+        }
+    }
+}
+
+/// What is left of the limits while a single roll is evaluated
+pub struct Budget<'a> {
+    /// The limits the roll must stay within
+    limits: &'a EvalLimits,
+    /// How many dice have been rolled so far
+    dice: BigInt,
+    /// When the roll began
+    started: Instant,
+    /// Whether every die of the roll is kept in its breakdown
+    keep_faces: bool,
+}
+
+impl<'a> Budget<'a> {
+    /// Start keeping track of a roll that has done nothing yet, and keeps every die in its breakdown
+    pub(crate) fn new(limits: &'a EvalLimits) -> Self {
+        Self {
+            limits,
+            dice: BigInt::from(0),
+            started: Instant::now(),
+            keep_faces: true,
+        }
+    }
+
+    /// Only keep the total of a roll of many dice that no modifier looks at, which is much faster to draw
+    pub(crate) fn without_faces(mut self) -> Self {
+        self.keep_faces = false;
+        self
+    }
+
+    /// Count dice that are about to be rolled, before any of them are
+    pub(crate) fn take_dice(&mut self, count: &BigInt) -> Result<(), RollError> {
+        self.dice += count;
+        self.check_dice(&self.dice)
+    }
+
+    /// Check that a number of dice is within the limit, without counting them as rolled
+    pub(crate) fn check_dice(&self, count: &BigInt) -> Result<(), RollError> {
+        match self.limits.max_dice {
+            Some(ref max_dice) if count > max_dice => Err(RollError::limit(format!("Exceeded the limit of {} dice in a roll", max_dice))),
+            _ => self.check_time(),
+        }
+    }
+
+    /// Whether every die of the roll is kept in its breakdown
+    pub(crate) fn keeps_faces(&self) -> bool {
+        self.keep_faces
+    }
+
+    /// Check that the roll has not run out of time
+    pub(crate) fn check_time(&self) -> Result<(), RollError> {
+        match self.limits.max_time {
+            Some(max_time) if self.started.elapsed() > max_time => Err(RollError::limit(format!("Exceeded the limit of {}ms for a roll", max_time.as_millis()))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Check that a value is not too large, and that the roll has not run out of time
+    pub(crate) fn check_value(&self, value: &BigInt) -> Result<(), RollError> {
+        match self.limits.max_bits {
+            Some(max_bits) if value.bits() > max_bits => Err(RollError::limit(format!("Exceeded the limit of {} bits for a value", max_bits))),
+            _ => self.check_time(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use num_bigint::BigInt;
+    use crate::limits::{EvalLimits, Budget};
+
+    #[test]
+    fn dice_and_bits() {
+        let limits = EvalLimits { max_dice: Some(BigInt::from(10)), max_bits: Some(8), ..EvalLimits::unlimited() };
+        let mut budget = Budget::new(&limits);
+        assert!(budget.take_dice(&BigInt::from(6)).is_ok());
+        assert!(budget.take_dice(&BigInt::from(4)).is_ok());
+        assert_eq!("Limit Error: Exceeded the limit of 10 dice in a roll", budget.take_dice(&BigInt::from(1)).unwrap_err().to_string());
+        assert!(budget.check_value(&BigInt::from(-255)).is_ok());
+        assert_eq!("limit", budget.check_value(&BigInt::from(256)).unwrap_err().kind());
+    }
+}
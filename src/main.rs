@@ -1,23 +1,584 @@
 use roll::tokenize;
-use roll::parse;
-use roll::evaluate;
+use roll::parse_with_limits;
+use roll::{evaluate_with_limits, evaluate_totals_with_limits};
+use roll::{Thunk, Start, RollResult};
+use roll::{distribution_with_limits, Distribution};
+use roll::histogram;
+use roll::{simulate, Simulation};
+use roll::Session;
+use roll::{result_json, error_json};
+use roll::diagnostic;
+use roll::RollError;
+use roll::EvalLimits;
+use std::io::{BufRead, IsTerminal};
+use std::process::ExitCode;
+use std::time::Duration;
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use rustyline::DefaultEditor;
+use rustyline::error::ReadlineError;
+use serde_json::json;
 
-fn main() {
-    let args: Vec<String> = std::env::args().skip(1).collect();
+/// The exit code when the options, or the input around an expression, could not be understood
+const EXIT_USAGE: u8 = 1;
+/// The exit code when an expression could not be tokenized or parsed
+const EXIT_SYNTAX: u8 = 2;
+/// The exit code when an expression could not be rolled, or its outcomes could not be weighed
+const EXIT_EVALUATION: u8 = 3;
+/// The exit code when reading or rolling an expression went past one of its limits
+const EXIT_LIMIT: u8 = 4;
 
-    let input = args.join(" ");
+/// The widest a histogram's bars may be drawn
+const MAX_WIDTH: usize = 1000;
 
-    let tokenized = tokenize(input.trim().to_string());
-    let parsed = parse(tokenized);
-    let evaluated = match parsed {
-        Ok(parse_tree) => evaluate(parse_tree),
+/// The options that were given on the command line
+struct Options {
+    /// Whether to show every die that was rolled
+    verbose: bool,
+    /// The seed for the random number generator, if the roll should be repeatable
+    seed: Option<u64>,
+    /// Whether to show the chance of every outcome instead of rolling
+    dist: bool,
+    /// Whether to show a summary of the outcomes instead of rolling
+    stats: bool,
+    /// The percentiles to include in the summary of the outcomes
+    percentiles: Vec<f64>,
+    /// Whether to draw a chart of the chance of every outcome instead of rolling
+    histogram: bool,
+    /// How many characters wide the longest bar of the chart is
+    width: usize,
+    /// How many times to roll the expression to estimate its outcomes, instead of working them out exactly
+    simulate: Option<usize>,
+    /// How many threads to share the simulated rolls between
+    threads: usize,
+    /// How many times to roll the expression, if it was given as an option
+    repeat: Option<usize>,
+    /// Whether to show repeated rolls from highest to lowest
+    sort: bool,
+    /// Whether to carry on to the next line of a batch after a line fails
+    continue_on_error: bool,
+    /// Whether to write results and errors as JSON, one object per line
+    json: bool,
+    /// How much work reading and rolling each expression may do
+    limits: EvalLimits,
+    /// The expression to evaluate
+    expression: String,
+}
+
+impl Options {
+    /// Read the options out of the command line arguments, or give back the first that could not be understood
+    /// along with whether JSON was asked for, so that the error can be written in the same format as any other
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, (String, bool)> {
+        let mut options = Self {
+            verbose: false,
+            seed: None,
+            dist: false,
+            stats: false,
+            percentiles: vec![5.0, 25.0, 75.0, 95.0],
+            histogram: false,
+            width: 50,
+            simulate: None,
+            threads: 1,
+            repeat: None,
+            sort: false,
+            continue_on_error: false,
+            json: false,
+            limits: EvalLimits::default(),
+            expression: String::new(),
+        };
+        let mut words = Vec::new();
+        let mut error = None;
+        while let Some(arg) = args.next() {
+            // Carry on past a bad option, since the format to write the error in may come after it
+            if let Err(message) = options.read(arg, &mut args, &mut words) {
+                error.get_or_insert(message);
+            }
+        }
+        options.expression = words.join(" ");
+        match error {
+            Some(message) => Err((message, options.json)),
+            None => Ok(options),
+        }
+    }
+
+    /// Read a single argument, along with the value after it if it is an option that takes one
+    fn read(&mut self, arg: String, args: &mut impl Iterator<Item = String>, words: &mut Vec<String>) -> Result<(), String> {
+        match arg.as_ref() {
+            "--verbose" | "-v" => self.verbose = true,
+            "--seed" => self.seed = Some(parse_value(&arg, args.next())?),
+            "--dist" => self.dist = true,
+            "--stats" => self.stats = true,
+            "--percentiles" => self.percentiles = parse_percentiles(&arg, args.next())?,
+            "--histogram" => self.histogram = true,
+            "--width" => self.width = parse_width(&arg, args.next())?,
+            "--simulate" => self.simulate = Some(parse_count(&arg, args.next())?),
+            "--threads" => self.threads = parse_count(&arg, args.next())?,
+            "--repeat" => self.repeat = Some(parse_value(&arg, args.next())?),
+            "--sort" => self.sort = true,
+            "--continue-on-error" => self.continue_on_error = true,
+            "--format" => self.json = parse_format(&arg, args.next())?,
+            "--max-dice" => self.limits.max_dice = Some(parse_value(&arg, args.next())?),
+            "--max-depth" => self.limits.max_depth = Some(parse_value(&arg, args.next())?),
+            "--max-bits" => self.limits.max_bits = Some(parse_value(&arg, args.next())?),
+            "--time-limit" => self.limits.max_time = Some(Duration::from_millis(parse_value(&arg, args.next())?)),
+            // Everything after a bare "--" is part of the expression, even if it looks like an option
+            "--" => words.extend(args.by_ref()),
+            _ if arg.starts_with("--") => return Err(format!("Unknown option {}", arg)),
+            _ => words.push(arg),
+        }
+        Ok(())
+    }
+}
+
+/// Split a leading repetition count such as "6x" off an expression
+fn split_repetition(expression: &str) -> Option<(&str, &str)> {
+    let expression = expression.trim_start();
+    let digits = expression.find(|ch: char| !ch.is_ascii_digit())?;
+    let rest = expression[digits..].trim_start().strip_prefix('x')?;
+    if digits == 0 {
+        None
+    } else {
+        Some((&expression[..digits], rest))
+    }
+}
+
+/// Read the value given to an option
+fn parse_value<T: std::str::FromStr>(option: &str, value: Option<String>) -> Result<T, String> {
+    match value {
+        Some(value) => value.parse().map_err(|_| format!("Invalid value for {}, found {}", option, value)),
+        None => Err(format!("Expected a value for {}, found end of arguments", option)),
+    }
+}
+
+/// Read a count of something that there must be at least one of
+fn parse_count(option: &str, value: Option<String>) -> Result<usize, String> {
+    match parse_value(option, value)? {
+        0 => Err(format!("Expected at least 1 for {}, found 0", option)),
+        count => Ok(count),
+    }
+}
+
+/// Read how wide a histogram's bars may be, refusing any too wide to draw
+fn parse_width(option: &str, value: Option<String>) -> Result<usize, String> {
+    match parse_value(option, value)? {
+        width if width <= MAX_WIDTH => Ok(width),
+        width => Err(format!("Expected a width of at most {} for {}, found {}", MAX_WIDTH, option, width)),
+    }
+}
+
+/// Read which format to write results in, returning whether it is JSON
+fn parse_format(option: &str, value: Option<String>) -> Result<bool, String> {
+    let value: String = parse_value(option, value)?;
+    match value.as_ref() {
+        "text" => Ok(false),
+        "json" => Ok(true),
+        _ => Err(format!("Expected \"text\" or \"json\" for {}, found {}", option, value)),
+    }
+}
+
+/// Read a comma separated list of percentiles
+fn parse_percentiles(option: &str, value: Option<String>) -> Result<Vec<f64>, String> {
+    let value: String = parse_value(option, value)?;
+    value.split(',')
+        .map(|percentile| match percentile.trim().parse::<f64>() {
+            Ok(percentile) if (0.0..=100.0).contains(&percentile) => Ok(percentile),
+            _ => Err(format!("Expected percentiles from 0 to 100 for {}, found {}", option, percentile)),
+        })
+        .collect()
+}
+
+/// Print a table of the chance of every outcome, and of rolling at least that outcome
+fn print_distribution(distribution: &Distribution) {
+    let width = distribution.outcomes()
+        .map(|(outcome, _)| outcome.to_string().len())
+        .fold("Outcome".len(), usize::max);
+    println!("{:>width$}  {:>8}  {:>8}", "Outcome", "Chance", "At Least", width = width);
+    for (outcome, _) in distribution.outcomes() {
+        println!(
+            "{:>width$}  {:>7.3}%  {:>7.3}%",
+            outcome,
+            100.0 * distribution.probability(outcome),
+            100.0 * distribution.probability_at_least(outcome),
+            width = width,
+        );
+    }
+}
+
+/// Print a summary of the outcomes, including the given percentiles
+fn print_statistics(distribution: &Distribution, percentiles: &[f64]) {
+    println!("{:<20}{}", "Minimum", distribution.min());
+    println!("{:<20}{}", "Maximum", distribution.max());
+    println!("{:<20}{:.3}", "Mean", distribution.mean());
+    println!("{:<20}{:.3}", "Standard Deviation", distribution.standard_deviation());
+    println!("{:<20}{}", "Median", distribution.median());
+    for &percentile in percentiles {
+        println!("{:<20}{}", format!("Percentile {}", percentile), distribution.percentile(percentile));
+    }
+}
+
+/// Print the 95% confidence interval of the mean of a simulation
+fn print_confidence(simulation: &Simulation) {
+    let (low, high) = simulation.mean_confidence_interval();
+    println!("{:<20}{}", "Samples", simulation.samples);
+    println!("{:<20}{:.3} to {:.3}", "Mean 95% CI", low, high);
+}
+
+/// Roll an expression many times and print whichever views of the outcomes were asked for
+fn print_simulation(parse_tree: &Thunk<Start>, samples: usize, options: &Options) -> Result<(), RollError> {
+    let simulation = simulate(parse_tree, samples, options.threads, options.seed, &options.limits)?;
+    // Summarise the outcomes unless some other view was asked for
+    if !(options.dist || options.histogram) {
+        print_statistics(&simulation.distribution, &options.percentiles);
+        print_confidence(&simulation);
+    } else {
+        print_outcomes(&simulation.distribution, options);
+        if options.stats {
+            print_confidence(&simulation);
+        }
+    }
+    Ok(())
+}
+
+/// Print whichever views of the outcomes were asked for
+fn print_outcomes(distribution: &Distribution, options: &Options) {
+    if options.dist {
+        print_distribution(distribution);
+    }
+    if options.stats {
+        print_statistics(distribution, &options.percentiles);
+    }
+    if options.histogram {
+        let outcomes = distribution.outcomes()
+            .map(|(outcome, _)| (outcome, distribution.probability(outcome)));
+        print!("{}", histogram(outcomes, options.width));
+    }
+}
+
+fn main() -> ExitCode {
+    let options = match Options::parse(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err((message, true)) => {
+            println!("{}", json!({ "error": usage_json(&message) }));
+            return ExitCode::from(EXIT_USAGE);
+        },
+        Err((message, false)) => {
+            eprintln!("{}", Failure::Usage(message));
+            return ExitCode::from(EXIT_USAGE);
+        },
+    };
+    // The same generator is kept for every roll, so a seed gives the same rolls each time
+    let mut rng = options.seed.map(StdRng::seed_from_u64);
+
+    let input = options.expression.trim();
+    // With nothing to roll, read from a pipe or file if there is one, or start a session otherwise
+    if input == "-" || (input.is_empty() && !std::io::stdin().is_terminal()) {
+        batch(&options, &mut rng)
+    } else if input.is_empty() {
+        repl(&options, &mut rng)
+    } else if let Err(error) = run(input, &options, &mut rng) {
+        print_error(input, None, &error, &options);
+        error.exit_code()
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+/// Something that stopped an input from being rolled
+enum Failure {
+    /// The input around the expression, such as a repetition count, was not understood
+    Usage(String),
+    /// The expression could not be read or rolled
+    Roll(RollError),
+}
+
+impl Failure {
+    /// The exit code that tells scripts what kind of failure this was
+    fn exit_code(&self) -> ExitCode {
+        ExitCode::from(match self {
+            Self::Usage(_) => EXIT_USAGE,
+            Self::Roll(RollError::TokenizeError { .. }) | Self::Roll(RollError::ParseError { .. }) => EXIT_SYNTAX,
+            Self::Roll(RollError::EvaluationError { .. }) | Self::Roll(RollError::DistributionError { .. }) => EXIT_EVALUATION,
+            Self::Roll(RollError::LimitError { .. }) => EXIT_LIMIT,
+        })
+    }
+}
+
+impl From<RollError> for Failure {
+    fn from(error: RollError) -> Self {
+        Self::Roll(error)
+    }
+}
+
+impl std::fmt::Display for Failure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Usage(message) => write!(f, "Usage Error: {}", message),
+            Self::Roll(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+/// Describe a usage error in the same way as any other error
+fn usage_json(message: &str) -> serde_json::Value {
+    json!({ "kind": "usage", "message": message, "span": null })
+}
+
+/// Print an error about an input, along with the line it was on if it came from a batch
+fn print_error(input: &str, line: Option<usize>, error: &Failure, options: &Options) {
+    if options.json {
+        let error = match error {
+            Failure::Usage(message) => usage_json(message),
+            Failure::Roll(error) => error_json(error),
+        };
+        let mut report = json!({ "input": input, "error": error });
+        if let Some(line) = line {
+            report["line"] = json!(line);
+        }
+        println!("{}", report);
+    } else {
+        // Color is only used when someone is there to see it, and not asked to be left out
+        let color = std::io::stderr().is_terminal() && std::env::var_os("NO_COLOR").is_none();
+        let report = match error {
+            Failure::Usage(_) => error.to_string(),
+            Failure::Roll(error) => diagnostic(input, error, color),
+        };
+        match line {
+            Some(line) => eprintln!("Line {}: {}", line, report),
+            None => eprintln!("{}", report),
+        }
+    }
+}
+
+/// Roll each line of the standard input in turn, stopping at the first error unless told not to,
+/// and give back the exit code of the first line that failed
+fn batch(options: &Options, rng: &mut Option<StdRng>) -> ExitCode {
+    let mut failed = None;
+    for (index, line) in std::io::stdin().lock().lines().enumerate() {
+        let line = match line {
+            Ok(line) => line,
+            Err(error) => {
+                eprintln!("Usage Error: Could not read line {}, {}", index + 1, error);
+                return ExitCode::from(EXIT_USAGE);
+            },
+        };
+        // Blank lines are skipped, so that lists of rolls can be spaced out
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Err(error) = run(line.trim(), options, rng) {
+            print_error(line.trim(), Some(index + 1), &error, options);
+            let code = *failed.get_or_insert(error.exit_code());
+            if !options.continue_on_error {
+                return code;
+            }
+        }
+    }
+    failed.unwrap_or(ExitCode::SUCCESS)
+}
+
+/// Roll lines one at a time until the user leaves, remembering variables and the last result
+fn repl(options: &Options, rng: &mut Option<StdRng>) -> ExitCode {
+    let mut editor = match DefaultEditor::new() {
+        Ok(editor) => editor,
         Err(error) => {
-            eprintln!("{}", error);
-            return;
+            eprintln!("Usage Error: Could not start an interactive session, {}", error);
+            return ExitCode::from(EXIT_USAGE);
         },
     };
-    match evaluated {
-        Ok(answer) => println!("{}", answer),
-        Err(error) => eprintln!("{}", error),
+    let mut session = Session::new();
+    loop {
+        let line = match editor.readline("roll> ") {
+            Ok(line) => line,
+            // Ctrl-C and Ctrl-D both end the session
+            Err(ReadlineError::Interrupted) | Err(ReadlineError::Eof) => return ExitCode::SUCCESS,
+            Err(error) => {
+                eprintln!("Usage Error: Could not read a line, {}", error);
+                return ExitCode::from(EXIT_USAGE);
+            },
+        };
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let _ = editor.add_history_entry(line);
+        match line {
+            "exit" | "quit" => return ExitCode::SUCCESS,
+            "vars" => {
+                for (name, value) in session.variables() {
+                    println!("{} = {}", name, value);
+                }
+                continue;
+            },
+            _ => (),
+        }
+        // An assignment rolls its expression once and keeps the result
+        let (name, expression) = match Session::assignment(line) {
+            Some((name, expression)) => (Some(name), expression.trim()),
+            None => (None, line),
+        };
+        // The repetition count is not part of the expression, so it is kept apart from any variables
+        let (count, expression) = match split_repetition(expression) {
+            Some((count, rest)) => (Some(count), rest),
+            None => (None, expression),
+        };
+        let substituted = match session.substitute(expression) {
+            Ok(substituted) => substituted,
+            Err(error) => {
+                // The error points into the expression, so that is what is shown
+                print_error(expression, None, &error.into(), options);
+                continue;
+            },
+        };
+        let expression = match count {
+            Some(count) => format!("{}x {}", count, substituted),
+            None => substituted,
+        };
+        let answer = match run(&expression, options, rng) {
+            Ok(answers) => answers.and_then(|answers| answers.last().map(|answer| answer.total.clone())),
+            Err(error) => {
+                print_error(&expression, None, &error, options);
+                continue;
+            },
+        };
+        if let Some(answer) = answer {
+            if let Some(name) = name {
+                if let Err(error) = session.set(name, answer.clone()) {
+                    print_error(line, None, &error.into(), options);
+                }
+            }
+            session.set_last(answer);
+        }
+    }
+}
+
+/// Roll a single expression, keeping every die only when they are shown
+fn roll<R: Rng>(parse_tree: &Thunk<Start>, rng: &mut R, options: &Options) -> Result<RollResult, RollError> {
+    if options.verbose || options.json {
+        evaluate_with_limits(parse_tree, rng, &options.limits)
+    } else {
+        evaluate_totals_with_limits(parse_tree, rng, &options.limits)
+    }
+}
+
+/// Roll an expression and print its results, giving back the results of the final repetition
+fn run(input: &str, options: &Options, rng: &mut Option<StdRng>) -> Result<Option<Vec<RollResult>>, Failure> {
+    // A leading count such as "6x" repeats the roll
+    let (repeat, expression) = match split_repetition(input) {
+        Some(_) if options.repeat.is_some() => {
+            return Err(Failure::Usage("Expected either a repetition count or --repeat, found both".to_string()));
+        },
+        Some((_, rest)) if rest.trim().is_empty() => {
+            return Err(Failure::Usage("Expected an expression after the repetition count".to_string()));
+        },
+        Some((count, rest)) => {
+            let repeat = parse_value("the repetition count", Some(count.to_string())).map_err(Failure::Usage)?;
+            // Blank out the count rather than cutting it off, so that spans still line up with the input
+            (Some(repeat), format!("{}{}", " ".repeat(input.len() - rest.len()), rest))
+        },
+        None => (options.repeat, input.to_string()),
+    };
+
+    let tokenized = tokenize(expression.clone());
+    let parse_trees = parse_with_limits(tokenized, &options.limits)?;
+    // Commas only ever separate expressions, so each expression's text lies between them
+    let labels: Vec<&str> = expression.split(',').map(str::trim).collect();
+
+    if options.simulate.is_some() || options.dist || options.stats || options.histogram {
+        if options.json {
+            return Err(Failure::Usage("JSON output is only available when rolling".to_string()));
+        }
+        if repeat.is_some() {
+            return Err(Failure::Usage("Repeated rolls are only available when rolling".to_string()));
+        }
+        for (index, (label, parse_tree)) in labels.iter().zip(&parse_trees).enumerate() {
+            // Tell the expressions apart when there are several
+            if parse_trees.len() > 1 {
+                if index > 0 {
+                    println!();
+                }
+                println!("{}", label);
+            }
+            match options.simulate {
+                // Estimate the outcomes by rolling many times
+                Some(samples) => print_simulation(parse_tree, samples, options)?,
+                // Weigh every outcome instead of rolling
+                None => print_outcomes(&distribution_with_limits(parse_tree, &options.limits)?, options),
+            }
+        }
+        return Ok(None);
+    }
+
+    let mut repetitions = Vec::new();
+    for _ in 0..repeat.unwrap_or(1) {
+        let mut answers = Vec::new();
+        for parse_tree in &parse_trees {
+            let answer = match rng {
+                Some(ref mut rng) => roll(parse_tree, rng, options)?,
+                None => roll(parse_tree, &mut rand::thread_rng(), options)?,
+            };
+            answers.push(answer);
+        }
+        repetitions.push(answers);
+    }
+    if options.sort {
+        // Repetitions are kept whole, so a list is ordered by its first expression
+        repetitions.sort_by(|a, b| {
+            let totals = |answers: &[RollResult]| answers.iter().map(|answer| answer.total.clone()).collect::<Vec<_>>();
+            totals(b).cmp(&totals(a))
+        });
+    }
+    if options.json {
+        let results: Vec<_> = repetitions.iter()
+            .enumerate()
+            .flat_map(|(index, answers)| labels.iter().zip(&parse_trees).zip(answers)
+                .map(move |((label, parse_tree), answer)| result_json(label, parse_tree, index + 1, answer)))
+            .collect();
+        println!("{}", json!({ "input": input, "results": results }));
+        return Ok(repetitions.pop());
+    }
+    for (index, answers) in repetitions.iter().enumerate() {
+        // Leave a gap between repetitions of a list
+        if index > 0 && parse_trees.len() > 1 {
+            println!();
+        }
+        for (label, answer) in labels.iter().zip(answers) {
+            if options.verbose {
+                // Show the expression with every die expanded before the answer
+                println!("{} → {} = {}", label, answer, answer.total);
+            } else {
+                println!("{}", answer.total);
+            }
+        }
+    }
+    Ok(repetitions.pop())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{split_repetition, Options};
+
+    /// Read options from a line of arguments split on spaces
+    fn options(line: &str) -> Result<Options, (String, bool)> {
+        Options::parse(line.split(' ').map(str::to_string))
+    }
+
+    #[test]
+    fn counts_of_at_least_one() {
+        assert_eq!(Some(10), options("--simulate 10 1d6").unwrap().simulate);
+        assert_eq!(Err(("Expected at least 1 for --simulate, found 0".to_string(), false)), options("--simulate 0 1d6").map(|_| ()));
+        assert_eq!(Err(("Expected at least 1 for --threads, found 0".to_string(), true)), options("--threads 0 --format json 1d6").map(|_| ()));
+    }
+
+    #[test]
+    fn repetition_count() {
+        assert_eq!(Some(("6", " 4d6kh3")), split_repetition("6x 4d6kh3"));
+        assert_eq!(Some(("5", " 1d20")), split_repetition("  5 x 1d20"));
+        assert_eq!(Some(("3", "")), split_repetition("3x"));
+        assert_eq!(Some(("2", "")), split_repetition("2 x"));
+        // A count must come first, and be followed by an "x"
+        assert_eq!(None, split_repetition("x 1d6"));
+        assert_eq!(None, split_repetition("2d6"));
+        assert_eq!(None, split_repetition("12"));
+        assert_eq!(None, split_repetition("1d6 x 2"));
     }
 }
@@ -1,11 +1,18 @@
 pub use crate::tokenize::Token;
 pub use crate::evaluate::Thunk;
-
-/// Type alias for a peekable stream of Tokens
-type TokenStream<'a> = std::iter::Peekable<std::slice::Iter<'a, Token>>;
+use crate::evaluate::Delayable;
+use crate::error::{RollError, Span};
+use crate::limits::EvalLimits;
 
 /// The starting point of the grammar, encompasses the entire input
 #[derive(Debug, PartialEq)]
+pub enum List {
+    /// List = Start, { ",", Start } ;
+    Expressions(Vec<Thunk<Start>>),
+}
+
+/// A single expression, which is rolled separately from the rest of the list
+#[derive(Debug, PartialEq)]
 pub enum Start {
     /// Start = AddLayer ;
     Base(Thunk<AddLayer>),
@@ -24,10 +31,19 @@ pub enum AddLayer {
 /// Represents the layer that multiplying and dividing are applied at
 #[derive(Debug, PartialEq)]
 pub enum MultLayer {
-    /// MultLayer = Roll ;
+    /// MultLayer = SignLayer ;
+    Base(Thunk<SignLayer>),
+    /// MultLayer = [ MultLayer, MultOperator ], SignLayer ;
+    Recurse(Thunk<Self>, MultOperator, Thunk<SignLayer>),
+}
+
+/// Represents the layer that a leading plus or minus sign is applied at
+#[derive(Debug, PartialEq)]
+pub enum SignLayer {
+    /// SignLayer = Roll ;
     Base(Thunk<Roll>),
-    /// MultLayer = [ MultLayer, MultOperator ], Roll ;
-    Recurse(Thunk<Self>, MultOperator, Thunk<Roll>),
+    /// SignLayer = AddOperator, SignLayer ;
+    Sign(AddOperator, Thunk<Self>),
 }
 
 /// Represents rolling a die, and the parts of a roll
@@ -35,8 +51,31 @@ pub enum MultLayer {
 pub enum Roll {
     /// Roll = SubExpression ;
     Base(Thunk<SubExpression>),
-    /// Roll = Roll, "d", SubExpression ;
+    /// Roll = Roll, RollOperator, SubExpression ;
+    /// Roll = "d", SubExpression ; (which rolls a single die, as if it were "1d")
     Recurse(Thunk<Self>, RollOperator, Thunk<SubExpression>),
+    /// Roll = Roll, Modifier ;
+    Modify(Thunk<Self>, Modifier),
+}
+
+/// Represents something that changes the dice of the roll before it
+#[derive(Debug, PartialEq)]
+pub enum Modifier {
+    /// Modifier = ExplodeOperator, [ Comparison ] ;
+    Explode(ExplodeOperator, Option<Comparison>),
+    /// Modifier = RerollOperator, [ Comparison ] ;
+    Reroll(RerollOperator, Option<Comparison>),
+    /// Modifier = ComparisonOperator, SubExpression ;
+    Success(Comparison),
+    /// Modifier = "f", Comparison ;
+    Failure(Comparison),
+}
+
+/// Represents a target that each die is checked against
+#[derive(Debug, PartialEq)]
+pub enum Comparison {
+    /// Comparison = [ ComparisonOperator ], SubExpression ;
+    Compare(ComparisonOperator, Thunk<SubExpression>),
 }
 
 /// Represents a sub-expression
@@ -56,7 +95,7 @@ pub enum Number {
 }
 
 /// Represents an operator that has addition-level precedence
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum AddOperator {
     /// AddOperator = "+" ;
     Add,
@@ -65,7 +104,7 @@ pub enum AddOperator {
 }
 
 /// Represents an operator that has multiplication-level precedence
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum MultOperator {
     /// MultOperator = "*";
     Multiply,
@@ -73,101 +112,467 @@ pub enum MultOperator {
     Divide,
 }
 
-/// This is included for symmetry with AddOperator and MultOperator
+/// Represents an operator that has roll-level precedence
 #[derive(Debug, PartialEq)]
 pub enum RollOperator {
     /// RollOperator = "d" ;
     D,
+    /// RollOperator = "kh" ;
+    KeepHighest,
+    /// RollOperator = "kl" ;
+    KeepLowest,
+    /// RollOperator = "dh" ;
+    DropHighest,
+    /// RollOperator = "dl" ;
+    DropLowest,
+}
+
+/// Represents the different ways that a die can explode
+#[derive(Debug, PartialEq)]
+pub enum ExplodeOperator {
+    /// ExplodeOperator = "!" ;
+    Explode,
+    /// ExplodeOperator = "!!" ;
+    Compound,
+    /// ExplodeOperator = "!p" ;
+    Penetrate,
+}
+
+/// Represents the different ways that a die can be rerolled
+#[derive(Debug, PartialEq)]
+pub enum RerollOperator {
+    /// RerollOperator = "r" ;
+    Reroll,
+    /// RerollOperator = "ro" ;
+    RerollOnce,
+}
+
+/// Represents the way a die is compared against a target
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ComparisonOperator {
+    /// ComparisonOperator = "=" ;
+    Equal,
+    /// ComparisonOperator = "<" ;
+    Less,
+    /// ComparisonOperator = "<=" ;
+    LessEqual,
+    /// ComparisonOperator = ">" ;
+    Greater,
+    /// ComparisonOperator = ">=" ;
+    GreaterEqual,
 }
 
-/// Parse a tokenized input into an expression tree
-pub fn parse(tokenized: Vec<Token>) -> Result<Thunk<Start>, String> {
-    // An iterator over the tokens that were read
-    let mut tokens = tokenized.iter().peekable();
-    // Parse a Start (this is the root of the grammar)
-    let start = Start::parse(&mut tokens);
+/// How many links of a chain of adding or multiplying count as one level of nesting, since those
+/// chains are worked through one link after another rather than by going deeper
+const LINKS_PER_LEVEL: usize = 16;
+
+/// A peekable stream of Tokens, which keeps track of where each one was in the input
+struct TokenStream<'a> {
+    /// The tokens that are left, along with their spans
+    tokens: std::iter::Peekable<std::slice::Iter<'a, (Token, Span)>>,
+    /// The span of the token that was read most recently
+    last: Span,
+    /// The empty span just past the final token
+    end: Span,
+    /// How many parentheses and signs the next token is nested within, in links of a chain
+    depth: usize,
+    /// The deepest level of nesting reached by what is being measured
+    deepest: usize,
+    /// The most levels of nesting a token may be within
+    max_depth: Option<usize>,
+}
+
+impl<'a> TokenStream<'a> {
+    fn new(tokens: &'a [(Token, Span)], max_depth: Option<usize>) -> Self {
+        let end = tokens.last().map_or(0, |(_, span)| span.end);
+        Self {
+            tokens: tokens.iter().peekable(),
+            last: Span::new(0, 0),
+            end: Span::new(end, end),
+            depth: 0,
+            deepest: 0,
+            max_depth,
+        }
+    }
+
+    /// Go into a layer of parentheses or a sign, failing if the tokens are already nested too deeply
+    fn enter(&mut self) -> Result<(), RollError> {
+        self.depth += LINKS_PER_LEVEL;
+        self.reach(0, self.last)
+    }
+
+    /// Record that something read at the current depth nests the given number of links deeper,
+    /// failing if that goes past the limit
+    fn reach(&mut self, height: usize, span: Span) -> Result<(), RollError> {
+        self.deepest = self.deepest.max(self.depth + height);
+        match self.max_depth {
+            Some(max_depth) if self.depth + height > max_depth.saturating_mul(LINKS_PER_LEVEL) => Err(RollError::limit(
+                format!("Exceeded the limit of {} levels of nesting", max_depth)
+            ).or_span(span)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Start measuring how deeply what is read next is nested, giving back the deepest level reached before
+    fn start_measure(&mut self) -> usize {
+        std::mem::replace(&mut self.deepest, self.depth)
+    }
+
+    /// Stop measuring, giving back how many links deeper than the current depth it went
+    fn stop_measure(&mut self, outer: usize) -> usize {
+        let height = self.deepest - self.depth;
+        self.deepest = self.deepest.max(outer);
+        height
+    }
+
+    /// Come out of the innermost layer of parentheses or sign
+    fn leave(&mut self) {
+        self.depth -= LINKS_PER_LEVEL;
+    }
+
+    /// Look at the next token without reading it
+    fn peek(&mut self) -> Option<&'a Token> {
+        self.tokens.peek().copied().map(|(token, _)| token)
+    }
+
+    /// Read the next token
+    fn next(&mut self) -> Option<&'a Token> {
+        match self.tokens.next() {
+            Some((token, span)) => {
+                self.last = *span;
+                Some(token)
+            },
+            None => {
+                self.last = self.end;
+                None
+            },
+        }
+    }
+
+    /// The span of the token that was read most recently, or the end of the input if there was none left
+    fn last_span(&self) -> Span {
+        self.last
+    }
+
+    /// The offset the next token starts at
+    fn next_start(&mut self) -> usize {
+        self.tokens.peek().map_or(self.end.start, |(_, span)| span.start)
+    }
+
+    /// The span from the given offset to the end of the token that was read most recently
+    fn since(&self, start: usize) -> Span {
+        Span::new(start, self.last.end)
+    }
+}
+
+/// Create the error for finding something other than what was expected, which is
+/// a tokenize error if what was found could not be understood at all
+fn unexpected(expected: &str, found: Option<&Token>, span: Span) -> RollError {
+    match found {
+        Some(Token::Undefined(text)) => RollError::tokenize(format!("Unknown character {:?}", text), span),
+        Some(Token::Identifier(text)) => RollError::parse(format!("Expected {}, found unknown word {:?}", expected, text), span),
+        Some(token) => RollError::parse(format!("Expected {}, found {:?}", expected, token), span),
+        None => RollError::parse(format!("Expected {}, found end of stream", expected), span),
+    }
+}
+
+/// Parse a tokenized input into an expression tree for each expression in the list
+pub fn parse(tokenized: Vec<(Token, Span)>) -> Result<Vec<Thunk<Start>>, RollError> {
+    parse_with_limits(tokenized, &EvalLimits::default())
+}
+
+/// Parse a tokenized input into an expression tree for each expression in the list,
+/// failing if parentheses are nested more deeply than the limits allow
+pub fn parse_with_limits(tokenized: Vec<(Token, Span)>, limits: &EvalLimits) -> Result<Vec<Thunk<Start>>, RollError> {
+    // A stream of the tokens that were read
+    let mut tokens = TokenStream::new(&tokenized, limits.max_depth);
+    // Parse a List (this is the root of the grammar)
+    let List::Expressions(expressions) = List::parse(&mut tokens)?;
     // If there are remaining tokens
     if let Some(token) = tokens.next() {
         // Report error
-        Err(format!("Parse Error: Expected end of stream, found {:?}", token))
+        Err(unexpected("end of stream", Some(token), tokens.last_span()))
     }
     // Otherwise
     else {
-        // Pass the parse-tree out
-        start
+        // Pass the parse-trees out
+        Ok(expressions)
+    }
+}
+
+impl List {
+    fn parse(tokens: &mut TokenStream) -> Result<Self, RollError> {
+        let mut expressions = vec![Start::parse(tokens)?];
+        while let Some(Token::ListSeparator(_)) = tokens.peek() {
+            tokens.next();
+            expressions.push(Start::parse(tokens)?);
+        }
+        Ok(Self::Expressions(expressions))
     }
 }
 
 impl Start {
-    fn parse(mut tokens: &mut TokenStream) -> Result<Thunk<Self>, String> {
+    #[allow(clippy::needless_borrow)]
+    fn parse(mut tokens: &mut TokenStream) -> Result<Thunk<Self>, RollError> {
+        let start = tokens.next_start();
         Ok(
             Thunk::new(
                 Self::Base(
                     AddLayer::parse(&mut tokens)?
                 )
-            )
+            ).with_span(tokens.since(start))
         )
     }
 }
 
 macro_rules! parse_left_assoc_infix {
     ($layer:ty, $base:ty, $operator:ty, $operator_token:pat) => {
-        fn parse(mut tokens: &mut TokenStream) -> Result<Thunk<Self>, String> {
+        fn parse(mut tokens: &mut TokenStream) -> Result<Thunk<Self>, RollError> {
+            let start = tokens.next_start();
+            let outer = tokens.start_measure();
             let base = <$base>::parse(&mut tokens)?;
+            let mut height = tokens.stop_measure(outer);
             match tokens.peek() {
                 Some($operator_token) => {
                     let mut left = Thunk::new(
                         <$layer>::Base(base)
-                    );
+                    ).with_span(tokens.since(start));
                     while let Some($operator_token) = tokens.peek() {
+                        let link_start = tokens.next_start();
                         let op = <$operator>::parse(&mut tokens)?;
+                        let outer = tokens.start_measure();
                         let right = <$base>::parse(&mut tokens)?;
+                        let right_height = tokens.stop_measure(outer);
+                        // Each link of a chain holds everything before it, but is worked through without going deeper
+                        height = height.max(right_height) + 1;
+                        tokens.reach(height, tokens.since(link_start))?;
                         left = Thunk::new(
                             <$layer>::Recurse(
                                 left,
                                 op,
                                 right,
                             )
-                        );
+                        ).with_span(tokens.since(start));
                     }
                     Ok(left)
                 },
                 _ => Ok(
                     Thunk::new(
                         <$layer>::Base(base),
-                    )
+                    ).with_span(tokens.since(start))
                 ),
             }
         }
     }
 }
 
+/// The last link of a chain, made of the chain before it, its operator and the operand after it
+pub(crate) type Link<'a, T> = (&'a Thunk<T>, <T as Chain>::Operator, &'a Thunk<<T as Chain>::Operand>);
+
+/// A layer made of operands joined by operators, which is worked through one link at a time
+/// so that a long chain needs no more room than a short one
+pub(crate) trait Chain: Delayable + Sized {
+    type Operand: Delayable;
+    type Operator: Copy;
+
+    /// The chain before the last link, along with that link, or the only operand if there is no link
+    fn split(&self) -> Result<Link<'_, Self>, &Thunk<Self::Operand>>;
+
+    /// The first operand, then each link in order, along with the Thunk holding the chain up to
+    /// that link if it is not the whole chain
+    #[allow(clippy::type_complexity)]
+    fn links(&self) -> (&Thunk<Self::Operand>, Vec<(Option<&Thunk<Self>>, Self::Operator, &Thunk<Self::Operand>)>) {
+        let mut links = Vec::new();
+        let mut holder = None;
+        let mut layer = self;
+        let first = loop {
+            match layer.split() {
+                Ok((left, op, right)) => {
+                    links.push((holder, op, right));
+                    holder = Some(left);
+                    layer = left.delayed();
+                },
+                Err(first) => break first,
+            }
+        };
+        links.reverse();
+        (first, links)
+    }
+}
+
+impl Chain for AddLayer {
+    type Operand = MultLayer;
+    type Operator = AddOperator;
+
+    fn split(&self) -> Result<Link<'_, Self>, &Thunk<MultLayer>> {
+        match self {
+            Self::Base(base_thunk) => Err(base_thunk),
+            Self::Recurse(left_thunk, op, right_thunk) => Ok((left_thunk, *op, right_thunk)),
+        }
+    }
+}
+
+impl Chain for MultLayer {
+    type Operand = SignLayer;
+    type Operator = MultOperator;
+
+    fn split(&self) -> Result<Link<'_, Self>, &Thunk<SignLayer>> {
+        match self {
+            Self::Base(base_thunk) => Err(base_thunk),
+            Self::Recurse(left_thunk, op, right_thunk) => Ok((left_thunk, *op, right_thunk)),
+        }
+    }
+}
+
 impl AddLayer {
     parse_left_assoc_infix!(AddLayer, MultLayer, AddOperator, Token::AddOperator(_));
 }
 
 impl MultLayer {
-    parse_left_assoc_infix!(MultLayer, Roll, MultOperator, Token::MultOperator(_));
+    parse_left_assoc_infix!(MultLayer, SignLayer, MultOperator, Token::MultOperator(_));
+}
+
+impl SignLayer {
+    fn parse(tokens: &mut TokenStream) -> Result<Thunk<Self>, RollError> {
+        let start = tokens.next_start();
+        match tokens.peek() {
+            // Signs may be stacked, as in "--3", with each applying to everything after it
+            Some(Token::AddOperator(_)) => {
+                let op = AddOperator::parse(tokens)?;
+                tokens.enter()?;
+                let signed = SignLayer::parse(tokens)?;
+                tokens.leave();
+                Ok(
+                    Thunk::new(
+                        SignLayer::Sign(op, signed)
+                    ).with_span(tokens.since(start))
+                )
+            },
+            _ => Ok(
+                Thunk::new(
+                    SignLayer::Base(Roll::parse(tokens)?)
+                ).with_span(tokens.since(start))
+            ),
+        }
+    }
 }
 
 impl Roll {
-    parse_left_assoc_infix!(Roll, SubExpression, RollOperator, Token::RollSeparator(_));
+    fn parse(tokens: &mut TokenStream) -> Result<Thunk<Self>, RollError> {
+        let start = tokens.next_start();
+        let (mut left, mut height) = match tokens.peek() {
+            // A roll with nothing before it is of a single die, so the count is filled in
+            Some(Token::RollSeparator(separator)) if separator == "d" => {
+                let before = Span::new(start, start);
+                (Thunk::new(
+                    Roll::Base(
+                        Thunk::new(
+                            SubExpression::Base(
+                                Thunk::new(
+                                    Number::StringRepresentation("1".to_string())
+                                ).with_span(before)
+                            )
+                        ).with_span(before)
+                    )
+                ).with_span(before), 0)
+            },
+            _ => {
+                let outer = tokens.start_measure();
+                let base = SubExpression::parse(tokens)?;
+                let height = tokens.stop_measure(outer);
+                (Thunk::new(
+                    Roll::Base(base)
+                ).with_span(tokens.since(start)), height)
+            },
+        };
+        // Rolls are left associative, and modifiers apply to everything before them
+        loop {
+            let link_start = tokens.next_start();
+            let (link, link_height) = match tokens.peek() {
+                Some(Token::RollSeparator(_)) => {
+                    let op = RollOperator::parse(tokens)?;
+                    let outer = tokens.start_measure();
+                    let right = SubExpression::parse(tokens)?;
+                    (Roll::Recurse(left, op, right), tokens.stop_measure(outer))
+                },
+                Some(Token::ExplodeOperator(_))
+                    | Some(Token::RerollOperator(_))
+                    | Some(Token::ComparisonOperator(_))
+                    | Some(Token::FailureOperator(_)) => {
+                    let outer = tokens.start_measure();
+                    let modifier = Modifier::parse(tokens)?;
+                    (Roll::Modify(left, modifier), tokens.stop_measure(outer))
+                },
+                _ => return Ok(left),
+            };
+            // Each roll or modifier is worked out within everything before it, so it is a whole level deeper
+            height = height.max(link_height) + LINKS_PER_LEVEL;
+            tokens.reach(height, tokens.since(link_start))?;
+            left = Thunk::new(link).with_span(tokens.since(start));
+        }
+    }
+}
+
+impl Modifier {
+    fn parse(tokens: &mut TokenStream) -> Result<Self, RollError> {
+        match tokens.peek() {
+            Some(Token::ExplodeOperator(_)) => {
+                let op = ExplodeOperator::parse(tokens)?;
+                Ok(Modifier::Explode(op, Comparison::parse_optional(tokens)?))
+            },
+            Some(Token::RerollOperator(_)) => {
+                let op = RerollOperator::parse(tokens)?;
+                Ok(Modifier::Reroll(op, Comparison::parse_optional(tokens)?))
+            },
+            Some(Token::ComparisonOperator(_)) => Ok(Modifier::Success(Comparison::parse(tokens)?)),
+            Some(Token::FailureOperator(_)) => {
+                tokens.next();
+                Ok(Modifier::Failure(Comparison::parse(tokens)?))
+            },
+            _ => {
+                let token = tokens.next();
+                Err(unexpected("Modifier", token, tokens.last_span()))
+            },
+        }
+    }
+}
+
+impl Comparison {
+    fn parse(tokens: &mut TokenStream) -> Result<Self, RollError> {
+        // A bare target means the die must be equal to it
+        let op = match tokens.peek() {
+            Some(Token::ComparisonOperator(_)) => ComparisonOperator::parse(tokens)?,
+            _ => ComparisonOperator::Equal,
+        };
+        Ok(Comparison::Compare(op, SubExpression::parse(tokens)?))
+    }
+
+    /// Parse a Comparison if the next token can start one
+    fn parse_optional(tokens: &mut TokenStream) -> Result<Option<Self>, RollError> {
+        match tokens.peek() {
+            Some(Token::ComparisonOperator(_)) | Some(Token::Number(_)) | Some(Token::OpenParenthesis(_)) => Ok(Some(Comparison::parse(tokens)?)),
+            _ => Ok(None),
+        }
+    }
 }
 
 impl SubExpression {
-    fn parse(mut tokens: &mut TokenStream) -> Result<Thunk<Self>, String> {
+    #[allow(clippy::needless_borrow)]
+    fn parse(mut tokens: &mut TokenStream) -> Result<Thunk<Self>, RollError> {
+        let start = tokens.next_start();
         match tokens.peek() {
             Some(Token::OpenParenthesis(_)) => {
                 tokens.next();
+                tokens.enter()?;
                 let nested = Start::parse(&mut tokens)?;
+                tokens.leave();
                 match tokens.next() {
                     Some(Token::CloseParenthesis(_)) => Ok(
                         Thunk::new(
                             SubExpression::Recurse(nested)
-                        )
+                        ).with_span(tokens.since(start))
                     ),
-                    Some(token) => Err(format!("Parse Error: Expected \")\", found {:?}", token)),
-                    None => Err("Parse Error: Expected \")\", found end of stream".to_string()),
+                    token => Err(unexpected("\")\"", token, tokens.last_span())),
                 }
             },
             Some(Token::Number(_)) => {
@@ -176,66 +581,262 @@ impl SubExpression {
                         SubExpression::Base(
                             Number::parse(&mut tokens)?
                         )
-                    )
+                    ).with_span(tokens.since(start))
                 )
             },
-            Some(_) => Err(format!("Parse Error: Expected nested start or \"(\", found: {:?}", tokens.next())),
-            None => Err("Parse Error: Expected nested start or \"(\", found end of stream".to_string()),
+            _ => {
+                let token = tokens.next();
+                Err(unexpected("nested start or \"(\"", token, tokens.last_span()))
+            },
         }
     }
 }
 
 impl Number {
-    fn parse(tokens: &mut TokenStream) -> Result<Thunk<Self>, String> {
+    fn parse(tokens: &mut TokenStream) -> Result<Thunk<Self>, RollError> {
         match tokens.next() {
             Some(Token::Number(number)) => Ok(
                 Thunk::new(
                     Number::StringRepresentation(number.clone()),
-                )
+                ).with_span(tokens.last_span())
             ),
-            Some(token) => Err(format!("Parse Error: Expected Number, found {:?}", token)),
-            None => Err("Parse Error: Expected Number, found end of stream".to_string()),
+            token => Err(unexpected("Number", token, tokens.last_span())),
         }
     }
 }
 
 impl AddOperator {
-    fn parse(tokens: &mut TokenStream) -> Result<Self, String> {
+    fn parse(tokens: &mut TokenStream) -> Result<Self, RollError> {
         match tokens.next() {
             Some(Token::AddOperator(op)) => match op.as_ref() {
                 "+" => Ok(AddOperator::Add),
                 "-" => Ok(AddOperator::Subtract),
-                _ => Err(format!("Parse Error: Expected \"+\" or \"-\", found {}", op)),
+                _ => Err(RollError::parse(format!("Expected \"+\" or \"-\", found {}", op), tokens.last_span())),
             }
-            Some(token) => Err(format!("Parse Error: Expected AddOperator, found {:?}", token)),
-            None => Err("Parse Error: Expected AddOperator, found end of stream".to_string()),
+            token => Err(unexpected("AddOperator", token, tokens.last_span())),
         }
     }
 }
 
 impl MultOperator {
-    fn parse(tokens: &mut TokenStream) -> Result<Self, String> {
+    fn parse(tokens: &mut TokenStream) -> Result<Self, RollError> {
         match tokens.next() {
             Some(Token::MultOperator(op)) => match op.as_ref() {
                 "*" => Ok(MultOperator::Multiply),
                 "/" => Ok(MultOperator::Divide),
-                _ => Err(format!("Parse Error: Expected \"*\" or \"/\", found {}", op)),
+                _ => Err(RollError::parse(format!("Expected \"*\" or \"/\", found {}", op), tokens.last_span())),
             }
-            Some(token) => Err(format!("Parse Error: Expected MultOperator, found {:?}", token)),
-            None => Err("Parse Error: Expected MultOperator, found end of stream".to_string()),
+            token => Err(unexpected("MultOperator", token, tokens.last_span())),
         }
     }
 }
 
 impl RollOperator {
-    fn parse(tokens: &mut TokenStream) -> Result<Self, String> {
+    fn parse(tokens: &mut TokenStream) -> Result<Self, RollError> {
         match tokens.next() {
             Some(Token::RollSeparator(op)) => match op.as_ref() {
                 "d" => Ok(RollOperator::D),
-                _ => Err(format!("Parse Error: Expected \"d\", found {}", op)),
+                "kh" => Ok(RollOperator::KeepHighest),
+                "kl" => Ok(RollOperator::KeepLowest),
+                "dh" => Ok(RollOperator::DropHighest),
+                "dl" => Ok(RollOperator::DropLowest),
+                _ => Err(RollError::parse(format!("Expected \"d\", \"kh\", \"kl\", \"dh\" or \"dl\", found {}", op), tokens.last_span())),
+            }
+            token => Err(unexpected("RollOperator", token, tokens.last_span())),
+        }
+    }
+}
+
+impl ExplodeOperator {
+    fn parse(tokens: &mut TokenStream) -> Result<Self, RollError> {
+        match tokens.next() {
+            Some(Token::ExplodeOperator(op)) => match op.as_ref() {
+                "!" => Ok(ExplodeOperator::Explode),
+                "!!" => Ok(ExplodeOperator::Compound),
+                "!p" => Ok(ExplodeOperator::Penetrate),
+                _ => Err(RollError::parse(format!("Expected \"!\", \"!!\" or \"!p\", found {}", op), tokens.last_span())),
+            }
+            token => Err(unexpected("ExplodeOperator", token, tokens.last_span())),
+        }
+    }
+}
+
+impl RerollOperator {
+    fn parse(tokens: &mut TokenStream) -> Result<Self, RollError> {
+        match tokens.next() {
+            Some(Token::RerollOperator(op)) => match op.as_ref() {
+                "r" => Ok(RerollOperator::Reroll),
+                "ro" => Ok(RerollOperator::RerollOnce),
+                _ => Err(RollError::parse(format!("Expected \"r\" or \"ro\", found {}", op), tokens.last_span())),
+            }
+            token => Err(unexpected("RerollOperator", token, tokens.last_span())),
+        }
+    }
+}
+
+impl ComparisonOperator {
+    fn parse(tokens: &mut TokenStream) -> Result<Self, RollError> {
+        match tokens.next() {
+            Some(Token::ComparisonOperator(op)) => match op.as_ref() {
+                "=" => Ok(ComparisonOperator::Equal),
+                "<" => Ok(ComparisonOperator::Less),
+                "<=" => Ok(ComparisonOperator::LessEqual),
+                ">" => Ok(ComparisonOperator::Greater),
+                ">=" => Ok(ComparisonOperator::GreaterEqual),
+                _ => Err(RollError::parse(format!("Expected \"=\", \"<\", \"<=\", \">\" or \">=\", found {}", op), tokens.last_span())),
             }
-            Some(token) => Err(format!("Parse Error: Expected RollOperator, found {:?}", token)),
-            None => Err("Parse Error: Expected RollOperator, found end of stream".to_string()),
+            token => Err(unexpected("ComparisonOperator", token, tokens.last_span())),
+        }
+    }
+}
+
+impl std::fmt::Display for Start {
+    /// Write out the expression in a normal form, with spaces only around adding and multiplying
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Base(base_thunk) => write!(f, "{}", base_thunk),
+        }
+    }
+}
+
+impl std::fmt::Display for AddLayer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (first, links) = self.links();
+        write!(f, "{}", first)?;
+        for (_, op, right_thunk) in links {
+            write!(f, " {} {}", op, right_thunk)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for MultLayer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (first, links) = self.links();
+        write!(f, "{}", first)?;
+        for (_, op, right_thunk) in links {
+            write!(f, " {} {}", op, right_thunk)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for SignLayer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Base(base_thunk) => write!(f, "{}", base_thunk),
+            Self::Sign(op, signed_thunk) => write!(f, "{}{}", op, signed_thunk),
+        }
+    }
+}
+
+impl std::fmt::Display for Roll {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Base(base_thunk) => write!(f, "{}", base_thunk),
+            Self::Recurse(left_thunk, op, right_thunk) => write!(f, "{}{}{}", left_thunk, op, right_thunk),
+            Self::Modify(left_thunk, modifier) => write!(f, "{}{}", left_thunk, modifier),
+        }
+    }
+}
+
+impl std::fmt::Display for Modifier {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Explode(op, Some(comparison)) => write!(f, "{}{}", op, comparison),
+            Self::Explode(op, None) => write!(f, "{}", op),
+            Self::Reroll(op, Some(comparison)) => write!(f, "{}{}", op, comparison),
+            Self::Reroll(op, None) => write!(f, "{}", op),
+            // A success target always needs its operator, even when it is "="
+            Self::Success(Comparison::Compare(op, value_thunk)) => write!(f, "{}{}", op, value_thunk),
+            Self::Failure(comparison) => write!(f, "f{}", comparison),
+        }
+    }
+}
+
+impl std::fmt::Display for Comparison {
+    /// An "=" is left out, since a bare target means the same thing
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Compare(ComparisonOperator::Equal, value_thunk) => write!(f, "{}", value_thunk),
+            Self::Compare(op, value_thunk) => write!(f, "{}{}", op, value_thunk),
+        }
+    }
+}
+
+impl std::fmt::Display for SubExpression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Base(number_thunk) => write!(f, "{}", number_thunk),
+            Self::Recurse(start_thunk) => write!(f, "({})", start_thunk),
+        }
+    }
+}
+
+impl std::fmt::Display for Number {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Self::StringRepresentation(string_rep) = self;
+        write!(f, "{}", string_rep)
+    }
+}
+
+impl std::fmt::Display for AddOperator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Add => write!(f, "+"),
+            Self::Subtract => write!(f, "-"),
+        }
+    }
+}
+
+impl std::fmt::Display for MultOperator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Multiply => write!(f, "*"),
+            Self::Divide => write!(f, "/"),
+        }
+    }
+}
+
+impl std::fmt::Display for RollOperator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::D => write!(f, "d"),
+            Self::KeepHighest => write!(f, "kh"),
+            Self::KeepLowest => write!(f, "kl"),
+            Self::DropHighest => write!(f, "dh"),
+            Self::DropLowest => write!(f, "dl"),
+        }
+    }
+}
+
+impl std::fmt::Display for ExplodeOperator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Explode => write!(f, "!"),
+            Self::Compound => write!(f, "!!"),
+            Self::Penetrate => write!(f, "!p"),
+        }
+    }
+}
+
+impl std::fmt::Display for RerollOperator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Reroll => write!(f, "r"),
+            Self::RerollOnce => write!(f, "ro"),
+        }
+    }
+}
+
+impl std::fmt::Display for ComparisonOperator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Equal => write!(f, "="),
+            Self::Less => write!(f, "<"),
+            Self::LessEqual => write!(f, "<="),
+            Self::Greater => write!(f, ">"),
+            Self::GreaterEqual => write!(f, ">="),
         }
     }
 }
@@ -243,35 +844,45 @@ impl RollOperator {
 #[cfg(test)]
 mod tests {
     use crate::parse::{parse, Token, Thunk};
-    use crate::parse::{Start, AddLayer, MultLayer, Roll, SubExpression, Number, AddOperator, MultOperator, RollOperator};
+    use crate::parse::{Start, AddLayer, MultLayer, SignLayer, Roll, SubExpression, Number, AddOperator, MultOperator, RollOperator};
+    use crate::parse::{Modifier, Comparison, ExplodeOperator, RerollOperator, ComparisonOperator};
+    use crate::error::Span;
+
+    /// Give each token a span of its own, as if they were written one after another
+    fn spanned(tokens: Vec<Token>) -> Vec<(Token, Span)> {
+        tokens.into_iter()
+            .enumerate()
+            .map(|(index, token)| (token, Span::new(index, index + 1)))
+            .collect()
+    }
 
     #[test]
     fn single_number() {
         assert_eq!(
-            Ok(Thunk::new(Start::Base(
+            Ok(vec![Thunk::new(Start::Base(
                 Thunk::new(AddLayer::Base(
                     Thunk::new(MultLayer::Base(
-                        Thunk::new(Roll::Base(
+                        Thunk::new(SignLayer::Base(Thunk::new(Roll::Base(
                             Thunk::new(SubExpression::Base(
                                 Thunk::new(Number::StringRepresentation(
                                     "1".to_string()
                                 ))
                             ))
-                        ))
+                        ))))
                     ))
                 ))
-            ))),
-            parse(vec![Token::Number("1".to_string())])
+            ))]),
+            parse(spanned(vec![Token::Number("1".to_string())]))
         )
     }
 
     #[test]
     fn multi_roll() {
         assert_eq!(
-	    Ok(Thunk::new(Start::Base(
+	    Ok(vec![Thunk::new(Start::Base(
 		Thunk::new(AddLayer::Base(
 		    Thunk::new(MultLayer::Base(
-			Thunk::new(Roll::Recurse(
+			Thunk::new(SignLayer::Base(Thunk::new(Roll::Recurse(
 			    Thunk::new(Roll::Recurse(
 				Thunk::new(Roll::Base(
 				    Thunk::new(SubExpression::Base(
@@ -293,11 +904,11 @@ mod tests {
 				    "3".to_string()
 				))
 			    ))
-			))
+			))))
 		    ))
 		))
-	    ))),
-            parse(
+	    ))]),
+            parse(spanned(
 		vec![
 		    Token::Number("1".to_string()),
 		    Token::RollSeparator("d".to_string()),
@@ -305,47 +916,84 @@ mod tests {
 		    Token::RollSeparator("d".to_string()),
 		    Token::Number("3".to_string()),
 		]
-	    )
+	    ))
         )
     }
 
+    #[test]
+    fn implicit_single_die() {
+        assert_eq!(
+            Ok(vec![Thunk::new(Start::Base(
+                Thunk::new(AddLayer::Base(
+                    Thunk::new(MultLayer::Base(
+                        Thunk::new(SignLayer::Base(
+                            Thunk::new(Roll::Recurse(
+                                Thunk::new(Roll::Base(
+                                    Thunk::new(SubExpression::Base(
+                                        Thunk::new(Number::StringRepresentation(
+                                            "1".to_string()
+                                        ))
+                                    ))
+                                )),
+                                RollOperator::D,
+                                Thunk::new(SubExpression::Base(
+                                    Thunk::new(Number::StringRepresentation(
+                                        "20".to_string()
+                                    ))
+                                ))
+                            ))
+                        ))
+                    ))
+                ))
+            ))]),
+            parse(spanned(vec![
+                Token::RollSeparator("d".to_string()),
+                Token::Number("20".to_string()),
+            ]))
+        );
+        let normalized = |input: &str| parse(crate::tokenize::tokenize(input.to_string())).unwrap()[0].to_string();
+        assert_eq!("(1d6)d6", normalized("(d6)d6"));
+        assert_eq!("-1d% + 1d4kh1", normalized("-d% + d4kh1"));
+        assert!(parse(crate::tokenize::tokenize("kh3".to_string())).is_err());
+    }
+
     #[test]
     fn multi_mult() {
         assert_eq!(
-            Ok(Thunk::new(Start::Base(
+            Ok(vec![Thunk::new(Start::Base(
                 Thunk::new(AddLayer::Base(
                     Thunk::new(MultLayer::Recurse(
                         Thunk::new(MultLayer::Recurse(
                             Thunk::new(MultLayer::Base(
-                                Thunk::new(Roll::Base(
+                                Thunk::new(SignLayer::Base(Thunk::new(Roll::Base(
                                     Thunk::new(SubExpression::Base(
                                         Thunk::new(Number::StringRepresentation(
                                             "1".to_string()
                                         ))
                                     ))
-                                ))
+                                ))))
                             )),
                             MultOperator::Multiply,
-                            Thunk::new(Roll::Base(
+                            Thunk::new(SignLayer::Base(Thunk::new(Roll::Base(
                                 Thunk::new(SubExpression::Base(
                                     Thunk::new(Number::StringRepresentation(
                                         "2".to_string()
                                     ))
                                 ))
-                            )),
+                            )))),
                         )),
                         MultOperator::Divide,
-                        Thunk::new(Roll::Base(
+                        Thunk::new(SignLayer::Base(Thunk::new(Roll::Base(
                             Thunk::new(SubExpression::Base(
                                 Thunk::new(Number::StringRepresentation(
                                     "3".to_string()
                                 ))
                             ))
-                        )),
+                        )))),
                     ))
                 ))
-            ))),
-            parse(
+            ))]),
+            parse(spanned(
 		vec![
 		    Token::Number("1".to_string()),
 		    Token::MultOperator("*".to_string()),
@@ -353,49 +1001,49 @@ mod tests {
 		    Token::MultOperator("/".to_string()),
 		    Token::Number("3".to_string()),
 		]
-	    )
+	    ))
         )
     }
 
     #[test]
     fn add_and_multiply() {
         assert_eq!(
-            Ok(Thunk::new(Start::Base(
+            Ok(vec![Thunk::new(Start::Base(
                 Thunk::new(AddLayer::Recurse(
                     Thunk::new(AddLayer::Base(
                         Thunk::new(MultLayer::Recurse(
                             Thunk::new(MultLayer::Base(
-                                Thunk::new(Roll::Base(
+                                Thunk::new(SignLayer::Base(Thunk::new(Roll::Base(
                                     Thunk::new(SubExpression::Base(
                                         Thunk::new(Number::StringRepresentation(
                                             "1".to_string()
                                         ))
                                     ))
-                                ))
+                                ))))
                             )),
                             MultOperator::Multiply,
-                            Thunk::new(Roll::Base(
+                            Thunk::new(SignLayer::Base(Thunk::new(Roll::Base(
                                 Thunk::new(SubExpression::Base(
                                     Thunk::new(Number::StringRepresentation(
                                         "2".to_string()
                                     ))
                                 ))
-                            )),
+                            )))),
                         ))
                     )),
                     AddOperator::Add,
                     Thunk::new(MultLayer::Base(
-                        Thunk::new(Roll::Base(
+                        Thunk::new(SignLayer::Base(Thunk::new(Roll::Base(
                             Thunk::new(SubExpression::Base(
                                 Thunk::new(Number::StringRepresentation(
                                     "3".to_string()
                                 ))
                             ))
-                        ))
+                        ))))
                     )),
                 ))
-            ))),
-            parse(
+            ))]),
+            parse(spanned(
 		vec![
 		    Token::Number("1".to_string()),
 		    Token::MultOperator("*".to_string()),
@@ -403,7 +1051,346 @@ mod tests {
 		    Token::AddOperator("+".to_string()),
 		    Token::Number("3".to_string()),
 		]
-	    )
+	    ))
+        )
+    }
+
+    #[test]
+    fn keep_highest() {
+        assert_eq!(
+            Ok(vec![Thunk::new(Start::Base(
+                Thunk::new(AddLayer::Base(
+                    Thunk::new(MultLayer::Base(
+                        Thunk::new(SignLayer::Base(Thunk::new(Roll::Recurse(
+                            Thunk::new(Roll::Recurse(
+                                Thunk::new(Roll::Base(
+                                    Thunk::new(SubExpression::Base(
+                                        Thunk::new(Number::StringRepresentation(
+                                            "4".to_string()
+                                        ))
+                                    ))
+                                )),
+                                RollOperator::D,
+                                Thunk::new(SubExpression::Base(
+                                    Thunk::new(Number::StringRepresentation(
+                                        "6".to_string()
+                                    ))
+                                ))
+                            )),
+                            RollOperator::KeepHighest,
+                            Thunk::new(SubExpression::Base(
+                                Thunk::new(Number::StringRepresentation(
+                                    "3".to_string()
+                                ))
+                            ))
+                        ))))
+                    ))
+                ))
+            ))]),
+            parse(spanned(
+                vec![
+                    Token::Number("4".to_string()),
+                    Token::RollSeparator("d".to_string()),
+                    Token::Number("6".to_string()),
+                    Token::RollSeparator("kh".to_string()),
+                    Token::Number("3".to_string()),
+                ]
+            ))
+        )
+    }
+
+    #[test]
+    fn explode_with_threshold() {
+        assert_eq!(
+            Ok(vec![Thunk::new(Start::Base(
+                Thunk::new(AddLayer::Base(
+                    Thunk::new(MultLayer::Base(
+                        Thunk::new(SignLayer::Base(Thunk::new(Roll::Modify(
+                            Thunk::new(Roll::Recurse(
+                                Thunk::new(Roll::Base(
+                                    Thunk::new(SubExpression::Base(
+                                        Thunk::new(Number::StringRepresentation(
+                                            "3".to_string()
+                                        ))
+                                    ))
+                                )),
+                                RollOperator::D,
+                                Thunk::new(SubExpression::Base(
+                                    Thunk::new(Number::StringRepresentation(
+                                        "6".to_string()
+                                    ))
+                                ))
+                            )),
+                            Modifier::Explode(
+                                ExplodeOperator::Explode,
+                                Some(Comparison::Compare(
+                                    ComparisonOperator::Greater,
+                                    Thunk::new(SubExpression::Base(
+                                        Thunk::new(Number::StringRepresentation(
+                                            "4".to_string()
+                                        ))
+                                    ))
+                                ))
+                            )
+                        ))))
+                    ))
+                ))
+            ))]),
+            parse(spanned(
+                vec![
+                    Token::Number("3".to_string()),
+                    Token::RollSeparator("d".to_string()),
+                    Token::Number("6".to_string()),
+                    Token::ExplodeOperator("!".to_string()),
+                    Token::ComparisonOperator(">".to_string()),
+                    Token::Number("4".to_string()),
+                ]
+            ))
+        )
+    }
+
+    #[test]
+    fn reroll_once_bare_target() {
+        assert_eq!(
+            Ok(vec![Thunk::new(Start::Base(
+                Thunk::new(AddLayer::Base(
+                    Thunk::new(MultLayer::Base(
+                        Thunk::new(SignLayer::Base(Thunk::new(Roll::Modify(
+                            Thunk::new(Roll::Recurse(
+                                Thunk::new(Roll::Base(
+                                    Thunk::new(SubExpression::Base(
+                                        Thunk::new(Number::StringRepresentation(
+                                            "2".to_string()
+                                        ))
+                                    ))
+                                )),
+                                RollOperator::D,
+                                Thunk::new(SubExpression::Base(
+                                    Thunk::new(Number::StringRepresentation(
+                                        "6".to_string()
+                                    ))
+                                ))
+                            )),
+                            Modifier::Reroll(
+                                RerollOperator::RerollOnce,
+                                Some(Comparison::Compare(
+                                    ComparisonOperator::Equal,
+                                    Thunk::new(SubExpression::Base(
+                                        Thunk::new(Number::StringRepresentation(
+                                            "1".to_string()
+                                        ))
+                                    ))
+                                ))
+                            )
+                        ))))
+                    ))
+                ))
+            ))]),
+            parse(spanned(
+                vec![
+                    Token::Number("2".to_string()),
+                    Token::RollSeparator("d".to_string()),
+                    Token::Number("6".to_string()),
+                    Token::RerollOperator("ro".to_string()),
+                    Token::Number("1".to_string()),
+                ]
+            ))
         )
     }
+
+    #[test]
+    fn successes_and_failures() {
+        assert_eq!(
+            Ok(vec![Thunk::new(Start::Base(
+                Thunk::new(AddLayer::Base(
+                    Thunk::new(MultLayer::Base(
+                        Thunk::new(SignLayer::Base(Thunk::new(Roll::Modify(
+                            Thunk::new(Roll::Modify(
+                                Thunk::new(Roll::Recurse(
+                                    Thunk::new(Roll::Base(
+                                        Thunk::new(SubExpression::Base(
+                                            Thunk::new(Number::StringRepresentation(
+                                                "10".to_string()
+                                            ))
+                                        ))
+                                    )),
+                                    RollOperator::D,
+                                    Thunk::new(SubExpression::Base(
+                                        Thunk::new(Number::StringRepresentation(
+                                            "10".to_string()
+                                        ))
+                                    ))
+                                )),
+                                Modifier::Success(Comparison::Compare(
+                                    ComparisonOperator::GreaterEqual,
+                                    Thunk::new(SubExpression::Base(
+                                        Thunk::new(Number::StringRepresentation(
+                                            "7".to_string()
+                                        ))
+                                    ))
+                                ))
+                            )),
+                            Modifier::Failure(Comparison::Compare(
+                                ComparisonOperator::Equal,
+                                Thunk::new(SubExpression::Base(
+                                    Thunk::new(Number::StringRepresentation(
+                                        "1".to_string()
+                                    ))
+                                ))
+                            ))
+                        ))))
+                    ))
+                ))
+            ))]),
+            parse(spanned(
+                vec![
+                    Token::Number("10".to_string()),
+                    Token::RollSeparator("d".to_string()),
+                    Token::Number("10".to_string()),
+                    Token::ComparisonOperator(">=".to_string()),
+                    Token::Number("7".to_string()),
+                    Token::FailureOperator("f".to_string()),
+                    Token::Number("1".to_string()),
+                ]
+            ))
+        )
+    }
+
+    #[test]
+    fn target_after_explode() {
+        // The target belongs to the explosion, so nothing counts successes
+        assert_eq!(
+            Ok(vec![Thunk::new(Start::Base(
+                Thunk::new(AddLayer::Base(
+                    Thunk::new(MultLayer::Base(
+                        Thunk::new(SignLayer::Base(Thunk::new(Roll::Modify(
+                            Thunk::new(Roll::Recurse(
+                                Thunk::new(Roll::Base(
+                                    Thunk::new(SubExpression::Base(
+                                        Thunk::new(Number::StringRepresentation(
+                                            "10".to_string()
+                                        ))
+                                    ))
+                                )),
+                                RollOperator::D,
+                                Thunk::new(SubExpression::Base(
+                                    Thunk::new(Number::StringRepresentation(
+                                        "10".to_string()
+                                    ))
+                                ))
+                            )),
+                            Modifier::Explode(ExplodeOperator::Explode, Some(Comparison::Compare(
+                                ComparisonOperator::GreaterEqual,
+                                Thunk::new(SubExpression::Base(
+                                    Thunk::new(Number::StringRepresentation(
+                                        "7".to_string()
+                                    ))
+                                ))
+                            )))
+                        ))))
+                    ))
+                ))
+            ))]),
+            parse(spanned(
+                vec![
+                    Token::Number("10".to_string()),
+                    Token::RollSeparator("d".to_string()),
+                    Token::Number("10".to_string()),
+                    Token::ExplodeOperator("!".to_string()),
+                    Token::ComparisonOperator(">=".to_string()),
+                    Token::Number("7".to_string()),
+                ]
+            ))
+        )
+    }
+
+    #[test]
+    fn comma_separated_list() {
+        let number = |n: &str| Thunk::new(Start::Base(
+            Thunk::new(AddLayer::Base(
+                Thunk::new(MultLayer::Base(
+                    Thunk::new(SignLayer::Base(Thunk::new(Roll::Base(
+                        Thunk::new(SubExpression::Base(
+                            Thunk::new(Number::StringRepresentation(n.to_string()))
+                        ))
+                    ))))
+                ))
+            ))
+        ));
+        assert_eq!(
+            Ok(vec![number("1"), number("2")]),
+            parse(spanned(vec![
+                Token::Number("1".to_string()),
+                Token::ListSeparator(",".to_string()),
+                Token::Number("2".to_string()),
+            ]))
+        );
+        assert!(parse(spanned(vec![Token::Number("1".to_string()), Token::ListSeparator(",".to_string())])).is_err());
+    }
+
+    #[test]
+    fn normal_form() {
+        let normalized = |input: &str| parse(crate::tokenize::tokenize(input.to_string())).unwrap()[0].to_string();
+        assert_eq!("4d6kh3 + 2 * (1d4)", normalized("4d6 kh3+2*( 1d4 )"));
+        assert_eq!("3d6!>5r1=6f<2", normalized("3d6!>5r=1=6f<2"));
+        assert_eq!("-1d4 + --3 * +(-2)", normalized("- 1d4 + --3*+(-2)"));
+    }
+
+    #[test]
+    fn unary_sign() {
+        assert_eq!(
+            Ok(vec![Thunk::new(Start::Base(
+                Thunk::new(AddLayer::Base(
+                    Thunk::new(MultLayer::Base(
+                        Thunk::new(SignLayer::Sign(
+                            AddOperator::Subtract,
+                            Thunk::new(SignLayer::Base(
+                                Thunk::new(Roll::Recurse(
+                                    Thunk::new(Roll::Base(
+                                        Thunk::new(SubExpression::Base(
+                                            Thunk::new(Number::StringRepresentation(
+                                                "1".to_string()
+                                            ))
+                                        ))
+                                    )),
+                                    RollOperator::D,
+                                    Thunk::new(SubExpression::Base(
+                                        Thunk::new(Number::StringRepresentation(
+                                            "4".to_string()
+                                        ))
+                                    ))
+                                ))
+                            ))
+                        ))
+                    ))
+                ))
+            ))]),
+            parse(spanned(vec![
+                Token::AddOperator("-".to_string()),
+                Token::Number("1".to_string()),
+                Token::RollSeparator("d".to_string()),
+                Token::Number("4".to_string()),
+            ]))
+        )
+    }
+
+    #[test]
+    fn error_spans() {
+        let error = |input: &str| parse(crate::tokenize::tokenize(input.to_string())).unwrap_err();
+        assert_eq!(Some(Span::new(6, 7)), error("2d6 + * 3").span());
+        assert_eq!("Parse Error: Expected nested start or \"(\", found MultOperator(\"*\")", error("2d6 + * 3").to_string());
+        assert_eq!(Some(Span::new(4, 4)), error("(1d4").span());
+        assert_eq!(Some(Span::new(3, 4)), error("1d4)").span());
+        let nested = format!("{}1{}", "(".repeat(101), ")".repeat(101));
+        assert_eq!(("limit", Some(Span::new(100, 101))), (error(&nested).kind(), error(&nested).span()));
+        // Every 16 links of adding or multiplying count as one level, while each roll counts as a whole level
+        assert!(parse(crate::tokenize::tokenize(vec!["1"; 1601].join("+"))).is_ok());
+        let chained = vec!["1"; 20_000].join("+");
+        assert_eq!(("limit", Some(Span::new(3201, 3203))), (error(&chained).kind(), error(&chained).span()));
+        let rolled = vec!["1"; 300_000].join("d");
+        assert_eq!(("limit", Some(Span::new(201, 203))), (error(&rolled).kind(), error(&rolled).span()));
+        assert!(parse(crate::tokenize::tokenize(format!("{}1{}", "(".repeat(94), "+1)".repeat(94)))).is_ok());
+        let nested_chains = format!("{}1{}", "(".repeat(95), "+1)".repeat(95));
+        assert_eq!("limit", error(&nested_chains).kind());
+    }
 }
@@ -0,0 +1,120 @@
+use std::collections::BTreeMap;
+use num_bigint::BigInt;
+use crate::error::RollError;
+use crate::tokenize::{tokenize, Token};
+
+/// The name that always refers to the result of the previous roll
+pub const LAST: &str = "last";
+
+/// The values remembered between the rolls of an interactive session
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Session {
+    /// The value of each variable that has been set
+    variables: BTreeMap<String, BigInt>,
+    /// The result of the previous roll, if there has been one
+    last: Option<BigInt>,
+}
+
+impl Session {
+    /// Create a session with no variables and no previous roll
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Every variable that has been set, in alphabetical order
+    pub fn variables(&self) -> impl Iterator<Item = (&String, &BigInt)> {
+        self.variables.iter()
+    }
+
+    /// Set a variable, so that later rolls may use its value
+    pub fn set(&mut self, name: &str, value: BigInt) -> Result<(), RollError> {
+        if !is_variable_name(name) {
+            return Err(RollError::evaluation(format!("Cannot name a variable {}", name)));
+        }
+        self.variables.insert(name.to_string(), value);
+        Ok(())
+    }
+
+    /// Remember the result of a roll, so that the next roll may use it as `last`
+    pub fn set_last(&mut self, value: BigInt) {
+        self.last = Some(value);
+    }
+
+    /// Split an assignment such as "bonus = 1d4" into the variable name and the expression
+    pub fn assignment(line: &str) -> Option<(&str, &str)> {
+        let (name, expression) = line.split_once('=')?;
+        let name = name.trim();
+        // Anything else before the "=" is a comparison, as in "3d6=6"
+        if is_variable_name(name) {
+            Some((name, expression))
+        } else {
+            None
+        }
+    }
+
+    /// Replace `last` and every variable in an expression with its value
+    pub fn substitute(&self, expression: &str) -> Result<String, RollError> {
+        let mut substituted = String::new();
+        // How much of the expression has been copied over so far
+        let mut copied = 0;
+        // Words are found the same way a roll is read, so that operators such as "kh" are left alone
+        for (token, span) in tokenize(expression.to_string()) {
+            if let Token::Identifier(word) = token {
+                let value = self.value_of(&word).map_err(|error| error.or_span(span))?;
+                substituted.push_str(&expression[copied..span.start]);
+                substituted.push_str(&value);
+                copied = span.end;
+            }
+        }
+        substituted.push_str(&expression[copied..]);
+        Ok(substituted)
+    }
+
+    /// The text to put in place of a word
+    fn value_of(&self, word: &str) -> Result<String, RollError> {
+        let value = match word {
+            LAST => self.last.as_ref()
+                .ok_or_else(|| RollError::evaluation("Nothing has been rolled yet, so there is no last result"))?,
+            _ => self.variables.get(word)
+                .ok_or_else(|| RollError::evaluation(format!("Unknown variable {}", word)))?,
+        };
+        // The parentheses keep a negative value's sign from joining up with what comes before it
+        Ok(format!("({})", value))
+    }
+}
+
+/// Check whether a word could name a variable, which it can if a roll would read it as a single word
+fn is_variable_name(word: &str) -> bool {
+    word != LAST && matches!(tokenize(word.to_string()).as_slice(), [(Token::Identifier(name), _)] if name == word)
+}
+
+#[cfg(test)]
+mod tests {
+    use num_bigint::BigInt;
+    use crate::session::Session;
+    use crate::error::Span;
+
+    #[test]
+    fn variables_and_last() {
+        let mut session = Session::new();
+        session.set("bonus", BigInt::from(5)).unwrap();
+        session.set("penalty", BigInt::from(-2)).unwrap();
+        session.set("hp", BigInt::from(30)).unwrap();
+        session.set_last(BigInt::from(12));
+        assert_eq!(Ok("1d20+(5)+(-2)".to_string()), session.substitute("1d20+bonus+penalty"));
+        assert_eq!(Ok("(12)*2".to_string()), session.substitute("last*2"));
+        assert_eq!(Ok("4d6kh3 + 2d6!p".to_string()), session.substitute("4d6kh3 + 2d6!p"));
+        assert_eq!(Ok("(30)/2 + 1d4dl1".to_string()), session.substitute("hp/2 + 1d4dl1"));
+        assert_eq!(Some(Span::new(5, 13)), session.substitute("1d20+strength").unwrap_err().span());
+    }
+
+    #[test]
+    fn assignments() {
+        assert_eq!(Some(("bonus", " 1d4")), Session::assignment("bonus = 1d4"));
+        assert_eq!(None, Session::assignment("3d6=6"));
+        assert_eq!(None, Session::assignment("1d20"));
+        assert!(Session::new().set("dh", BigInt::from(1)).is_err());
+        assert!(Session::new().set("last", BigInt::from(1)).is_err());
+        assert!(Session::new().substitute("last").is_err());
+    }
+}
@@ -0,0 +1,103 @@
+use num_bigint::BigInt;
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use crate::parse::Start;
+use crate::evaluate::Thunk;
+use crate::distribution::Distribution;
+use crate::error::RollError;
+use crate::limits::{EvalLimits, Budget};
+
+/// How many standard errors either side of the mean a 95% confidence interval spans
+const CONFIDENCE_95: f64 = 1.96;
+/// The most threads a simulation is shared between, however many are asked for.
+/// This is fixed rather than taken from the machine, so that seeded simulations are the same everywhere
+const MAX_THREADS: usize = 64;
+
+/// The outcomes of evaluating an expression many times
+#[derive(Debug, Clone, PartialEq)]
+pub struct Simulation {
+    /// How often each outcome came up
+    pub distribution: Distribution,
+    /// How many times the expression was evaluated
+    pub samples: usize,
+}
+
+impl Simulation {
+    /// The range the true mean lies within, with 95% confidence
+    pub fn mean_confidence_interval(&self) -> (f64, f64) {
+        let mean = self.distribution.mean();
+        let error = CONFIDENCE_95 * self.distribution.standard_deviation() / (self.samples as f64).sqrt();
+        (mean - error, mean + error)
+    }
+}
+
+/// Evaluate an expression tree many times, spread across the given number of threads,
+/// and count how often each outcome comes up, with each roll held to the limits
+pub fn simulate(tree: &Thunk<Start>, samples: usize, threads: usize, seed: Option<u64>, limits: &EvalLimits) -> Result<Simulation, RollError> {
+    if samples == 0 {
+        return Err(RollError::distribution("Expected at least 1 sample to simulate, found 0"));
+    }
+    let threads = threads.clamp(1, samples.min(MAX_THREADS));
+    // Give each thread its own generator, so the same seed and threads give the same outcomes
+    let mut seeder = seed.map(StdRng::seed_from_u64);
+    let rngs: Vec<StdRng> = (0..threads)
+        .map(|_| match seeder {
+            Some(ref mut seeder) => StdRng::seed_from_u64(seeder.gen()),
+            None => StdRng::from_entropy(),
+        })
+        .collect();
+    let outcomes = std::thread::scope(|scope| {
+        let workers: Vec<_> = rngs.into_iter()
+            .enumerate()
+            .map(|(index, mut rng)| {
+                // Share out the samples, with the first threads taking any left over
+                let count = samples / threads + usize::from(index < samples % threads);
+                scope.spawn(move || sample(tree, count, &mut rng, limits))
+            })
+            .collect();
+        workers.into_iter()
+            .map(|worker| worker.join().expect("A simulation thread panicked"))
+            .collect::<Result<Vec<_>, RollError>>()
+    })?;
+    Ok(Simulation {
+        distribution: Distribution::uniform(outcomes.into_iter().flatten()),
+        samples,
+    })
+}
+
+/// Evaluate an expression tree the given number of times
+fn sample<R: Rng>(tree: &Thunk<Start>, count: usize, rng: &mut R, limits: &EvalLimits) -> Result<Vec<BigInt>, RollError> {
+    // Only the totals are looked at, so the dice of huge rolls need not be kept
+    (0..count).map(|_| Ok(tree.evaluate(rng, &mut Budget::new(limits).without_faces())?.total)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use num_bigint::BigInt;
+    use crate::tokenize::tokenize;
+    use crate::parse::parse;
+    use crate::simulate::{simulate, MAX_THREADS};
+    use crate::limits::EvalLimits;
+
+    #[test]
+    fn same_seed_same_outcomes() {
+        let tree = parse(tokenize("3d6!".to_string())).unwrap().remove(0);
+        let first = simulate(&tree, 1000, 4, Some(7), &EvalLimits::default()).unwrap();
+        assert_eq!(first, simulate(&tree, 1000, 4, Some(7), &EvalLimits::default()).unwrap());
+        assert_eq!(1000, first.samples);
+        assert!(*first.distribution.min() >= BigInt::from(3));
+        // Asking for more threads than are allowed is the same as asking for the most allowed
+        assert_eq!(
+            simulate(&tree, 1000, MAX_THREADS, Some(7), &EvalLimits::default()).unwrap(),
+            simulate(&tree, 1000, usize::MAX, Some(7), &EvalLimits::default()).unwrap()
+        );
+    }
+
+    #[test]
+    fn mean_within_interval() {
+        let tree = parse(tokenize("2d6+3".to_string())).unwrap().remove(0);
+        let simulation = simulate(&tree, 20000, 2, Some(1), &EvalLimits::default()).unwrap();
+        let (low, high) = simulation.mean_confidence_interval();
+        assert!(low < 10.0 && 10.0 < high, "Expected 10 within {} to {}", low, high);
+    }
+}
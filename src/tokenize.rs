@@ -1,7 +1,9 @@
+use crate::error::Span;
+
 /// Represents a single segment of the input string
 #[derive(Debug, PartialEq)]
 pub enum Token {
-    /// The separator character used in a roll
+    /// The separator used in a roll, or a keep/drop modifier following one
     RollSeparator(String),
     /// The character used to open a layer of recursion
     OpenParenthesis(String),
@@ -13,45 +15,177 @@ pub enum Token {
     AddOperator(String),
     /// A multiplication or division operator
     MultOperator(String),
+    /// An operator that makes dice explode
+    ExplodeOperator(String),
+    /// An operator that makes dice be rerolled
+    RerollOperator(String),
+    /// An operator that makes dice count as failures
+    FailureOperator(String),
+    /// An operator that compares a die against a target
+    ComparisonOperator(String),
+    /// The character used to separate expressions in a list
+    ListSeparator(String),
+    /// A run of letters that is not made of roll operators, such as a keyword
+    Identifier(String),
     /// An undefined token, brought along for later processing
     Undefined(String),
 }
 
-/// Take an input string and turn it into a list of tokens
-pub fn tokenize(input: String) -> Vec<Token> {
+impl Token {
+    /// The text the token was read from
+    pub fn text(&self) -> &str {
+        match self {
+            Token::RollSeparator(text)
+                | Token::OpenParenthesis(text)
+                | Token::CloseParenthesis(text)
+                | Token::Number(text)
+                | Token::AddOperator(text)
+                | Token::MultOperator(text)
+                | Token::ExplodeOperator(text)
+                | Token::RerollOperator(text)
+                | Token::FailureOperator(text)
+                | Token::ComparisonOperator(text)
+                | Token::ListSeparator(text)
+                | Token::Identifier(text)
+                | Token::Undefined(text) => text,
+        }
+    }
+}
+
+/// Take an input string and turn it into a list of tokens, each with the span of the input it was read from
+#[allow(clippy::needless_borrow)]
+pub fn tokenize(input: String) -> Vec<(Token, Span)> {
     // The tokens that will be returned from the function
     let mut tokens = Vec::new();
     // A list of characters to turn into tokens
     let mut characters = input.chars().peekable();
+    // The byte offset of the next character
+    let mut offset = 0;
     // This pattern is used so that the list can be advanced from inside the loop
-    while let Some(ch) = characters.peek() {
+    while let Some(&ch) = characters.peek() {
         // Ignore whitespace
         if ch.is_whitespace() {
+            offset += ch.len_utf8();
             (&mut characters).next();
             continue;
         }
-        // Add a token to the list
-        tokens.push(
-            // What kind of character is it?
-            match ch {
-                'd' => Token::RollSeparator(characters.next().unwrap().to_string()),
-                '(' => Token::OpenParenthesis(characters.next().unwrap().to_string()),
-                ')' => Token::CloseParenthesis(characters.next().unwrap().to_string()),
-                // If it is a numerical constant,
-                '0'..='9' | '%' => Token::Number(
-                    // Hand off to the parsing function
-                    parse_number(&mut characters)
-                ),
-                '+' | '-' => Token::AddOperator(characters.next().unwrap().to_string()),
-                '*' | '/' => Token::MultOperator(characters.next().unwrap().to_string()),
-                // If it matched no pattern, bring it for processing
-                _ => Token::Undefined(ch.to_string()),
-            }
-        );
+        // What kind of character is it?
+        let token = match ch {
+            // Letters are read as a whole word, unless the word is made of roll operators
+            _ if is_letter(ch) && !is_operator_word(characters.clone()) => Token::Identifier(
+                parse_identifier(&mut characters)
+            ),
+            // A "d" may begin either a roll or a drop modifier, and a "k" a keep modifier
+            'd' | 'k' => Token::RollSeparator(parse_keep_drop(&mut characters)),
+            '(' => Token::OpenParenthesis(characters.next().unwrap().to_string()),
+            ')' => Token::CloseParenthesis(characters.next().unwrap().to_string()),
+            // If it is a numerical constant,
+            '0'..='9' | '%' => Token::Number(
+                // Hand off to the parsing function
+                parse_number(&mut characters)
+            ),
+            '+' | '-' => Token::AddOperator(characters.next().unwrap().to_string()),
+            '*' | '/' => Token::MultOperator(characters.next().unwrap().to_string()),
+            '!' => Token::ExplodeOperator(parse_explode(&mut characters)),
+            'r' => Token::RerollOperator(parse_reroll(&mut characters)),
+            'f' => Token::FailureOperator(characters.next().unwrap().to_string()),
+            '<' | '>' | '=' => Token::ComparisonOperator(parse_comparison(&mut characters)),
+            ',' => Token::ListSeparator(characters.next().unwrap().to_string()),
+            // If it matched no pattern, bring it for processing
+            _ => Token::Undefined(characters.next().unwrap().to_string()),
+        };
+        // Every token is made of exactly the text it was read from
+        let span = Span::new(offset, offset + token.text().len());
+        offset = span.end;
+        tokens.push((token, span));
     }
     tokens
 }
 
+/// Checks whether a character may be part of a word.
+fn is_letter(ch: char) -> bool {
+    ch.is_alphabetic() || ch == '_'
+}
+
+/// Checks whether the word at the start of the characters is made only of roll operators, such as "kh" or "ro".
+fn is_operator_word(mut characters: std::iter::Peekable<std::str::Chars<'_>>) -> bool {
+    while let Some(&ch) = characters.peek() {
+        match ch {
+            'd' => {
+                parse_keep_drop(&mut characters);
+            },
+            // A "k" is only an operator along with its highest/lowest marker
+            'k' if parse_keep_drop(&mut characters).len() == 2 => (),
+            'r' => {
+                parse_reroll(&mut characters);
+            },
+            'f' => {
+                characters.next();
+            },
+            // The word has ended without any other letter
+            _ if !is_letter(ch) => break,
+            _ => return false,
+        }
+    }
+    true
+}
+
+/// Parses a run of letters into a string.
+fn parse_identifier(characters: &mut std::iter::Peekable<std::str::Chars<'_>>) -> String {
+    let mut accumulator = Vec::new();
+    // Take the characters that are letters
+    while let Some(&c) = characters.peek() {
+        if !is_letter(c) {
+            break;
+        }
+        accumulator.push(c);
+        characters.next();
+    }
+    accumulator.iter().collect()
+}
+
+/// Parses a "d" or "k", along with a trailing "h" or "l" if there is one.
+fn parse_keep_drop(characters: &mut std::iter::Peekable<std::str::Chars<'_>>) -> String {
+    let mut accumulator = vec![characters.next().unwrap()];
+    // Take the highest/lowest marker if it follows
+    if let Some(c @ 'h') | Some(c @ 'l') = characters.peek() {
+        accumulator.push(*c);
+        characters.next();
+    }
+    accumulator.iter().collect()
+}
+
+/// Parses a "!", along with a trailing "!" or "p" if there is one.
+fn parse_explode(characters: &mut std::iter::Peekable<std::str::Chars<'_>>) -> String {
+    let mut accumulator = vec![characters.next().unwrap()];
+    // Take the compounding/penetrating marker if it follows
+    if let Some(c @ '!') | Some(c @ 'p') = characters.peek() {
+        accumulator.push(*c);
+        characters.next();
+    }
+    accumulator.iter().collect()
+}
+
+/// Parses an "r", along with a trailing "o" if there is one.
+fn parse_reroll(characters: &mut std::iter::Peekable<std::str::Chars<'_>>) -> String {
+    let mut accumulator = vec![characters.next().unwrap()];
+    // Take the reroll-once marker if it follows
+    if characters.peek() == Some(&'o') {
+        accumulator.push(characters.next().unwrap());
+    }
+    accumulator.iter().collect()
+}
+
+/// Parses a "<", ">" or "=", along with a trailing "=" if it makes "<=" or ">=".
+fn parse_comparison(characters: &mut std::iter::Peekable<std::str::Chars<'_>>) -> String {
+    let mut accumulator = vec![characters.next().unwrap()];
+    // Only "<" and ">" can be followed by "="
+    if accumulator[0] != '=' && characters.peek() == Some(&'=') {
+        accumulator.push(characters.next().unwrap());
+    }
+    accumulator.iter().collect()
+}
+
 /// Parses the next numerical constant into a string.
 fn parse_number(characters: &mut std::iter::Peekable<std::str::Chars<'_>>) -> String {
     let mut accumulator = Vec::new();
@@ -67,3 +201,48 @@ fn parse_number(characters: &mut std::iter::Peekable<std::str::Chars<'_>>) -> St
     }
     accumulator.iter().collect()
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::tokenize::{tokenize, Token};
+    use crate::parse::parse;
+    use crate::error::Span;
+
+    #[test]
+    fn unknown_characters_and_words() {
+        assert_eq!(
+            vec![
+                (Token::Number("2".to_string()), Span::new(0, 1)),
+                (Token::RollSeparator("d".to_string()), Span::new(1, 2)),
+                (Token::Number("6".to_string()), Span::new(2, 3)),
+                (Token::Identifier("x".to_string()), Span::new(3, 4)),
+                (Token::Undefined("$".to_string()), Span::new(4, 5)),
+                (Token::RollSeparator("kh".to_string()), Span::new(6, 8)),
+                (Token::Identifier("strength".to_string()), Span::new(9, 17)),
+            ],
+            tokenize("2d6x$ kh strength".to_string())
+        );
+    }
+
+    #[test]
+    fn malformed_input() {
+        // Each input once made the tokenizer spin forever or panic
+        let cases = [
+            ("2d6x", "parse", Span::new(3, 4)),
+            ("2d6 + x", "parse", Span::new(6, 7)),
+            ("1d20+dex", "parse", Span::new(5, 8)),
+            ("k", "parse", Span::new(0, 1)),
+            ("4d6k3", "parse", Span::new(3, 4)),
+            ("h", "parse", Span::new(0, 1)),
+            ("3d6 # fire", "tokenize", Span::new(4, 5)),
+            ("1d6 ~", "tokenize", Span::new(4, 5)),
+            ("é", "parse", Span::new(0, 2)),
+            ("1d6 🎲", "tokenize", Span::new(4, 8)),
+            ("2d6!px", "parse", Span::new(5, 6)),
+        ];
+        for (input, kind, span) in cases.iter() {
+            let error = parse(tokenize(input.to_string())).unwrap_err();
+            assert_eq!((*kind, Some(*span)), (error.kind(), error.span()), "{}", input);
+        }
+    }
+}